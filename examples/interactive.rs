@@ -13,17 +13,17 @@ use std::io::prelude::*;
 fn main() {
     env_logger::init().unwrap();
     let mut core = Core::new().unwrap();
-    let mut modem = HuaweiModem::new_from_path("/dev/ttyUSB2", &core.handle()).unwrap();
+    let modem = HuaweiModem::new_from_path("/dev/ttyUSB2", &core.handle()).unwrap();
     let urcfut = modem.take_urc_rx().unwrap().for_each(|item| {
         println!("URC: {:?}", item);
         Ok(())
     });
     core.handle().spawn(urcfut);
     println!("Setting textmode false...");
-    let fut = cmd::sms::set_sms_textmode(&mut modem, false);
+    let fut = cmd::sms::set_sms_textmode(&modem, false);
     println!("Result: {:?}", core.run(fut));
     println!("Setting new message indications...");
-    let fut = cmd::sms::set_new_message_indications(&mut modem,
+    let fut = cmd::sms::set_new_message_indications(&modem,
                                                     cmd::sms::NewMessageNotification::SendDirectlyOrBuffer,
                                                     cmd::sms::NewMessageStorage::StoreAndNotify);
     println!("Result: {:?}", core.run(fut));
@@ -38,7 +38,7 @@ fn main() {
         let ln = ln.unwrap();
         if ln == "read" {
             println!("Reading messages...");
-            let fut = cmd::sms::list_sms_pdu(&mut modem, cmd::sms::MessageStatus::All)
+            let fut = cmd::sms::list_sms_pdu(&modem, cmd::sms::MessageStatus::All)
                 .map(|v| {
                     for msg in v {
                         println!("Message: {:?}", msg);
@@ -61,7 +61,7 @@ fn main() {
         }
         if ln == "del" {
             println!("Deleting messags...");
-            let fut = cmd::sms::del_sms_pdu(&mut modem, cmd::sms::DeletionOptions::DeleteAll);
+            let fut = cmd::sms::del_sms_pdu(&modem, cmd::sms::DeletionOptions::DeleteAll);
             println!("Result: {:?}", core.run(fut));
             continue;
         }
@@ -75,7 +75,7 @@ fn main() {
             let msg = Pdu::make_simple_message(recipient.clone(), msg);
             println!("PDU: {:?}", msg);
             println!("Encoded PDU: {}", HexData(&msg.as_bytes().0));
-            let fut = cmd::sms::send_sms_pdu(&mut modem, &msg);
+            let fut = cmd::sms::send_sms_pdu(&modem, &msg);
             println!("Result: {:?}", core.run(fut));
         }
     }
@@ -0,0 +1,87 @@
+//! Derive macros for the `huawei-modem` crate.
+//!
+//! You probably want to use these through `huawei_modem::convert`, rather than depending on
+//! this crate directly.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields};
+
+/// Derive `FromAtValue` for a struct or a fieldless enum.
+///
+/// Structs are decoded from an `AtValue::Array`, taking one element per field, in the order the
+/// fields are declared. Enums are decoded from an `AtValue::Integer`, and must also derive
+/// `FromPrimitive`.
+#[proc_macro_derive(FromAtValue)]
+pub fn derive_from_at_value(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let names = match data.fields {
+                Fields::Named(ref fields) => fields.named.iter()
+                    .map(|f| f.ident.as_ref().unwrap().to_string())
+                    .collect(),
+                _ => (0..data.fields.len()).map(|i| i.to_string()).collect::<Vec<_>>()
+            };
+            let values = names.iter().enumerate()
+                .map(|(i, n)| {
+                    let field = format!("{}.{}", name, n);
+                    quote!(::huawei_modem::convert::field(fields, #i, #field)?)
+                })
+                .collect::<Vec<_>>();
+            let construct = match data.fields {
+                Fields::Named(ref fields) => {
+                    let idents = fields.named.iter().map(|f| f.ident.as_ref().unwrap());
+                    quote!(#name { #(#idents: #values),* })
+                },
+                Fields::Unnamed(_) => quote!(#name(#(#values),*)),
+                Fields::Unit => quote!(#name)
+            };
+            if values.is_empty() {
+                // don't bind `fields`, so unit structs don't get an unused variable warning
+                quote! {
+                    let _ = value;
+                    Ok(#construct)
+                }
+            }
+            else {
+                quote! {
+                    let fields = ::huawei_modem::convert::fields(value);
+                    Ok(#construct)
+                }
+            }
+        },
+        Data::Enum(ref data) => {
+            if let Some(v) = data.variants.iter().find(|v| !v.fields.is_empty()) {
+                return syn::Error::new_spanned(v, "FromAtValue can only be derived for enums without fields")
+                    .to_compile_error()
+                    .into();
+            }
+            quote!(::huawei_modem::convert::from_integer(value))
+        },
+        Data::Union(_) => {
+            return syn::Error::new(Span::call_site(), "FromAtValue can't be derived for unions")
+                .to_compile_error()
+                .into();
+        }
+    };
+    let params = input.generics.type_params()
+        .map(|p| p.ident.clone())
+        .collect::<Vec<_>>();
+    for p in params {
+        input.generics.make_where_clause().predicates
+            .push(parse_quote!(#p: ::huawei_modem::convert::FromAtValue));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let ret = quote! {
+        impl #impl_generics ::huawei_modem::convert::FromAtValue for #name #ty_generics #where_clause {
+            fn from_at_value(value: &::huawei_modem::at::AtValue) -> ::huawei_modem::HuaweiResult<Self> {
+                #body
+            }
+        }
+    };
+    ret.into()
+}
@@ -0,0 +1,84 @@
+//! Support for using the library with `std::future` and async/await (the `async` feature).
+//!
+//! The library is still built on futures 0.1 internally; this module just provides the glue
+//! to make it usable from modern async code, via the `compat` layer in futures 0.3.
+use std::future::Future as StdFuture;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use futures::Future;
+use futures::sync::mpsc;
+use futures03::compat::{Compat, Future01CompatExt};
+use futures03::io::{AsyncRead, AsyncWrite};
+use futures03::FutureExt;
+use tokio_codec::Decoder;
+use crate::{HuaweiModem, CommandFuture, ModemResponseFuture};
+use crate::charset::Charset;
+use crate::codec::AtCodec;
+use crate::errors::HuaweiResult;
+use crate::urc::{UrcHub, UrcPublisher};
+use crate::future::{self, ModemResponse, HuaweiModemFuture};
+
+impl<T> StdFuture for CommandFuture<T> {
+    type Output = HuaweiResult<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        (&mut self.get_mut().inner).compat().poll_unpin(cx)
+    }
+}
+impl StdFuture for ModemResponseFuture {
+    type Output = HuaweiResult<ModemResponse>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.get_mut().compat().poll_unpin(cx)
+    }
+}
+impl HuaweiModem {
+    /// Start talking to a modem over a futures 0.3 asynchronous byte stream, for use with modern
+    /// async runtimes (requires the `async` feature).
+    ///
+    /// Returns the `HuaweiModem`, and a future that drives communication with the modem, which
+    /// you should spawn on your runtime (e.g. with `tokio::spawn`). The driver works exactly
+    /// like the background future spawned by `new_from_stream`, and completes when the modem
+    /// goes away or the `HuaweiModem` is dropped.
+    ///
+    /// To use a Tokio 1.x stream (e.g. from `tokio-serial`), wrap it with
+    /// `tokio_util::compat::TokioAsyncReadCompatExt::compat` first.
+    pub fn new_from_async_stream<S>(stream: S) -> (Self, impl StdFuture<Output = ()> + Send) where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        let framed = AtCodec::default().framed(Compat::new(stream));
+        let (tx, rx) = mpsc::unbounded();
+        let hub = Arc::new(Mutex::new(UrcHub::default()));
+        let charset = Arc::new(Mutex::new(Charset::default()));
+        let fut = HuaweiModemFuture::new(framed, future::default_init(), rx, UrcPublisher::new(hub.clone()), charset.clone())
+            .map_err(|e| {
+                error!("HuaweiModemFuture failed: {}", e);
+                error!("Backtrace: {}", e.backtrace());
+            })
+            .compat()
+            .map(|_| ());
+        (Self::from_parts(tx, hub, charset, None), fut)
+    }
+}
+#[cfg(test)]
+mod test {
+    use crate::HuaweiModem;
+    use crate::cmd;
+    use crate::cmd::network::SignalQuality;
+    use crate::simulator::ModemSimulator;
+    use futures03::compat::AsyncRead01CompatExt;
+    use futures03::executor::LocalPool;
+    use futures03::task::LocalSpawnExt;
+
+    #[test]
+    fn await_commands() {
+        let sim = ModemSimulator::new();
+        sim.set_signal_quality(SignalQuality { rssi: 12, ber: 99 });
+        let (modem, driver) = HuaweiModem::new_from_async_stream(sim.stream().compat());
+        let mut pool = LocalPool::new();
+        pool.spawner().spawn_local(driver).unwrap();
+        let sq = pool.run_until(async {
+            cmd::network::get_signal_quality(&modem).await
+        }).unwrap();
+        assert_eq!(sq, SignalQuality { rssi: 12, ber: 99 });
+    }
+}
@@ -1,7 +1,9 @@
 //! Types for dealing with AT commands and replies.
 use crate::error_codes::CmsError;
 use std::fmt;
+use std::time::Duration;
 use crate::errors::{HuaweiError, HuaweiResult};
+use crate::convert::FromAtValue;
 /// An AT result code, which indicates the completion of a command.
 #[derive(Fail, Debug, Clone, PartialEq, Eq, is_enum_variant)]
 pub enum AtResultCode {
@@ -126,11 +128,25 @@ at_value_impl!(AtValue,
 ///
 /// This `impl` is directly used for formatting `AtValue`s when making
 /// AT commands.
+///
+/// Characters in strings that would otherwise end the string or the command line (`"`, `\`,
+/// and all control characters, including CR, LF, Ctrl-Z and ESC) are escaped as a backslash
+/// followed by two hex digits (e.g. `\22`), as described in 3GPP TS 27.007. (Control characters
+/// can turn up in perfectly normal strings in the GSM character set, where `@` is 0x00.)
 impl fmt::Display for AtValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::AtValue::*;
         match *self {
-            String(ref st) => write!(f, "\"{}\"", st)?,
+            String(ref st) => {
+                write!(f, "\"")?;
+                for c in st.chars() {
+                    match c {
+                        '"' | '\\' | '\x00'..='\x1F' | '\x7F' => write!(f, "\\{:02X}", c as u32)?,
+                        c => write!(f, "{}", c)?
+                    }
+                }
+                write!(f, "\"")?;
+            },
             Integer(i) => write!(f, "{}", i)?,
             Range((a, b)) => write!(f, "{}-{}", a, b)?,
             Unknown(ref st) => write!(f, "{}", st)?,
@@ -167,16 +183,35 @@ pub enum AtResponse {
     },
     /// An AT result code, indicating the completion of a command.
     ResultCode(AtResultCode),
+    /// The `> ` prompt, indicating that the modem is waiting for the payload of a command (see
+    /// `AtCommand::WithPrompt`).
+    Prompt,
+    /// A line of data following an information response, like the PDU after a `+CMGL` header.
+    ///
+    /// The line is left exactly as the modem sent it (i.e. it isn't parsed, or decoded from the
+    /// modem's character set).
+    Data(String),
     /// Some other unknown response.
     Unknown(String)
 }
+/// An information response, along with any data lines that followed it (see
+/// `AtResponsePacket::information_responses`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtInformationResponse<'a> {
+    /// The name of the response (e.g. `+CMGL`).
+    pub param: &'a str,
+    /// The value of the response.
+    pub response: &'a AtValue,
+    /// The lines of data that followed the response, if any.
+    pub data: Vec<&'a str>
+}
 /// The complete set of responses to an issued AT command.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AtResponsePacket {
     /// The various `AtResponses` issued.
     ///
-    /// Note that this will only contain 'expected' `InformationResponse`s,
-    /// as well as any `Unknown` responses. 'Expected' values are values
+    /// Note that this will only contain 'expected' `InformationResponse`s (and the `Data` lines
+    /// following them), as well as any `Unknown` responses. 'Expected' values are values
     /// that were expected as a result of the command issued - for more
     /// information, see the `AtCommand` documentation.
     pub responses: Vec<AtResponse>,
@@ -207,6 +242,38 @@ impl AtResponsePacket {
             None => Err(HuaweiError::ExpectedResponse(resp.into()))
         }
     }
+    /// Like `extract_named_response`, but also decodes the value into a `T` (see the `convert`
+    /// module).
+    pub fn decode_named_response<T: FromAtValue>(&self, resp: &str) -> HuaweiResult<T> {
+        T::from_at_value(self.extract_named_response(resp)?)
+    }
+    /// Get all of the `InformationResponse`s that have a given `resp` as their `param`, along
+    /// with the data lines following each one (for commands like `AT+CMGL`, which return a header
+    /// line followed by some data for each entry).
+    ///
+    /// Also invokes `self.assert_ok()?`, to verify that the response was successful.
+    pub fn information_responses(&self, resp: &str) -> HuaweiResult<Vec<AtInformationResponse<'_>>> {
+        self.assert_ok()?;
+        let mut ret: Vec<AtInformationResponse> = vec![];
+        let mut cur = false;
+        for r in self.responses.iter() {
+            match *r {
+                AtResponse::InformationResponse { ref param, ref response } => {
+                    cur = resp == param;
+                    if cur {
+                        ret.push(AtInformationResponse { param, response, data: vec![] });
+                    }
+                },
+                AtResponse::Data(ref line) => {
+                    if let (true, Some(last)) = (cur, ret.last_mut()) {
+                        last.data.push(line);
+                    }
+                },
+                _ => cur = false
+            }
+        }
+        Ok(ret)
+    }
     /// Returns `HuaweiError::AtError(self.status.clone())` if the status code was not `Ok`.
     pub fn assert_ok(&self) -> HuaweiResult<()> {
         if self.status.is_ok() {
@@ -217,7 +284,63 @@ impl AtResponsePacket {
         }
     }
 }
+/// Fail with `HuaweiError::UnsafeValue` if `st` contains any control characters, or any of
+/// `forbidden`.
+fn check_chars(st: &str, forbidden: &[char], reason: &'static str) -> HuaweiResult<()> {
+    if st.chars().any(|c| c.is_control() || forbidden.contains(&c)) {
+        return Err(HuaweiError::UnsafeValue { value: st.into(), reason });
+    }
+    Ok(())
+}
+fn check_name(name: &str) -> HuaweiResult<()> {
+    check_chars(name, &[' ', '"', ';', '=', '?', ','], "invalid command name")
+}
+impl AtValue {
+    /// Check that this value can be safely written out as part of an AT command (see
+    /// `AtCommand::validate`).
+    pub fn validate(&self) -> HuaweiResult<()> {
+        match *self {
+            AtValue::Unknown(ref st) => check_chars(st, &['"', ';', ','], "unquoted values can't contain control characters, quotes, commas or semicolons"),
+            AtValue::BracketedArray(ref vals) | AtValue::Array(ref vals) => {
+                vals.iter().try_for_each(|v| v.validate())
+            },
+            _ => Ok(())
+        }
+    }
+}
 impl AtCommand {
+    /// Check that this command can be sent to the modem safely, i.e. that nothing in it could end
+    /// the command line early, and smuggle in another command.
+    ///
+    /// Strings are always safe (since they're escaped; see `AtValue`'s `Display` implementation),
+    /// but command names, unquoted values, raw text and payloads aren't, and fail with
+    /// `HuaweiError::UnsafeValue` if they contain control characters, or any of the delimiters
+    /// that could be used to break out of them. Payloads can contain line breaks, but not the
+    /// Ctrl-Z or ESC characters used to end them.
+    ///
+    /// `HuaweiModem` checks every command with this before sending it.
+    pub fn validate(&self) -> HuaweiResult<()> {
+        match *self {
+            AtCommand::Equals { ref param, ref value } => {
+                check_name(param)?;
+                value.validate()
+            },
+            AtCommand::Execute { ref command } => check_name(command),
+            AtCommand::Read { ref param } | AtCommand::Test { ref param } => check_name(param),
+            AtCommand::Basic { ref command, .. } => check_name(command),
+            AtCommand::Text { ref text, .. } => check_chars(text, &[], "raw commands can't contain control characters"),
+            AtCommand::WithPrompt { ref command, ref payload } => {
+                command.validate()?;
+                if payload.contains(['\x1A', '\x1B']) {
+                    return Err(HuaweiError::UnsafeValue {
+                        value: payload.clone(),
+                        reason: "payloads can't contain Ctrl-Z or ESC"
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
     /// Get the set of 'expected' `InformationResponse`s for this command.
     ///
     /// This is used by the library to filter out URCs (Unsolicited Response Codes) - basically,
@@ -227,6 +350,7 @@ impl AtCommand {
     /// - For `Equals`, `Read`, and `Test`, this is the value of `vec![param]`.
     /// - For `Execute` and `Basic`, this is the value of `vec![command]`.
     /// - For `Text`, this is the value of `expected`.
+    /// - For `WithPrompt`, this is the `expected()` of the inner `command`.
     pub fn expected(&self) -> Vec<String> {
         match *self {
             AtCommand::Equals { ref param, .. } => vec![param.clone()],
@@ -235,8 +359,33 @@ impl AtCommand {
             AtCommand::Test { ref param } => vec![param.clone()],
             AtCommand::Basic { ref command, .. } => vec![command.clone()],
             AtCommand::Text { ref expected, .. } => expected.clone(),
+            AtCommand::WithPrompt { ref command, .. } => command.expected(),
         }
     }
+    /// Get the default amount of time to wait for this command to complete before giving up on it.
+    ///
+    /// Most commands complete in a matter of milliseconds, but some (like network scans with
+    /// `AT+COPS=?`, or sending messages) involve talking to the network, and can take a lot longer.
+    /// This is a rough guess based on the name of the command; if you know better, use
+    /// `HuaweiModem::send_raw_with_timeout`.
+    pub fn default_timeout(&self) -> Duration {
+        let secs = match *self {
+            AtCommand::WithPrompt { ref command, .. } => return command.default_timeout(),
+            AtCommand::Test { ref param } if param == "+COPS" => 180,
+            AtCommand::Equals { ref param, .. } if param == "+COPS" => 120,
+            AtCommand::Basic { ref command, .. } if command == "D" || command == "A" => 60,
+            _ => {
+                let names = self.expected();
+                let name = names.first().map(|x| x as &str).unwrap_or("");
+                match name {
+                    "+CMGS" | "+CMGW" | "+CMSS" | "+CUSD" => 60,
+                    "+CMGL" | "+CMGR" | "+CPBR" | "+COPN" | "+CLAC" | "+CFUN" => 30,
+                    _ => 10
+                }
+            }
+        };
+        Duration::from_secs(secs)
+    }
 }
 /// An AT command.
 #[derive(Debug, Clone, PartialEq, Eq, is_enum_variant)]
@@ -280,6 +429,19 @@ pub enum AtCommand {
         text: String,
         /// The set of 'expected' `InformationResponse`s to this command.
         expected: Vec<String>
+    },
+    /// Send `command`, wait for the modem to send a `> ` prompt, and then send `payload`
+    /// terminated by Ctrl-Z.
+    ///
+    /// This is how commands like `AT+CMGS` (send message) work. The payload is only sent once the
+    /// prompt actually arrives; if the command times out or its response future is dropped
+    /// beforehand, the prompt is answered with ESC instead, aborting the command.
+    WithPrompt {
+        /// The command to send first (e.g. `AT+CMGS=<length>`).
+        command: Box<AtCommand>,
+        /// The payload to write once the modem sends the `> ` prompt (followed by Ctrl-Z, which
+        /// shouldn't be included here).
+        payload: String
     }
 }
 /// Writes the `AtCommand` out, as it would appear on the command line.
@@ -297,8 +459,36 @@ impl fmt::Display for AtCommand {
                     write!(f, "{}", n)?;
                 }
             },
-            Text { ref text, .. } => write!(f, "{}", text)?
+            Text { ref text, .. } => write!(f, "{}", text)?,
+            WithPrompt { ref command, .. } => write!(f, "{}", command)?
         }
         Ok(())
     }
 }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escaping_and_validation() {
+        let cmd = AtCommand::Equals {
+            param: "+CMGS".into(),
+            value: AtValue::String("+44\";\r\nAT+CFUN=0\x1A\\".into())
+        };
+        assert_eq!(cmd.to_string(), r#"AT+CMGS="+44\22;\0D\0AAT+CFUN=0\1A\5C""#);
+        assert!(cmd.validate().is_ok());
+        let bad = vec![
+            AtCommand::Execute { command: "+CSQ\r\nAT+CFUN=0".into() },
+            AtCommand::Read { param: "+CREG;+CFUN".into() },
+            AtCommand::Equals { param: "+CMGD".into(), value: AtValue::Unknown("1;+CFUN=0".into()) },
+            AtCommand::Text { text: "AT\rATZ".into(), expected: vec![] },
+            AtCommand::WithPrompt { command: Box::new(cmd), payload: "hi\x1A".into() }
+        ];
+        for cmd in bad {
+            match cmd.validate() {
+                Err(HuaweiError::UnsafeValue { .. }) => {},
+                x => panic!("{:?} should have been rejected: {:?}", cmd, x)
+            }
+        }
+    }
+}
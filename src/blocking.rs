@@ -0,0 +1,253 @@
+//! A blocking, synchronous interface to a modem.
+//!
+//! If you're writing a simple tool that just wants to run a command and get the answer, setting
+//! up a reactor and chaining futures together is a bit of a faff. `BlockingModem` owns its own
+//! `tokio_core` reactor, and exposes the operations in the `cmd` module as plain methods that
+//! block until the modem responds.
+//!
+//! Note that the reactor (and thus the connection to the modem) only runs while you're calling
+//! one of `BlockingModem`'s methods, so URCs will only be picked up when you do so.
+use std::path::Path;
+use std::time::Duration;
+use futures::{Future, Stream};
+use futures::future::Either;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures_timer::Delay;
+use tokio_core::reactor::Core;
+use tokio_io::{AsyncRead, AsyncWrite};
+use crate::{HuaweiModem, InitStepResult};
+use crate::at::{AtCommand, AtResponse, AtResponsePacket};
+use crate::capabilities::ModemCapabilities;
+use crate::charset::Charset;
+use crate::cmd;
+use crate::cmd::AtCommandSpec;
+use crate::cmd::network::{RegistrationState, ModemOperationMode, PinState, SignalQuality};
+use crate::cmd::network::{HuaweiSignalQuality, ExtendedSignalQuality, SystemInfo, ExtendedSystemInfo};
+use crate::cmd::network::{CurrentOperator, NetworkOperator, OperatorSelection, OperatorFormat};
+use crate::cmd::sms::{MessageStatus, NewMessageNotification, NewMessageStorage, DeletionOptions, SmsMessage};
+use crate::errors::{HuaweiError, HuaweiResult};
+use crate::pdu::{AddressType, Pdu};
+use crate::serial::SerialConfig;
+
+/// A modem connection that runs its own reactor, and blocks on every operation.
+pub struct BlockingModem {
+    core: Core,
+    modem: HuaweiModem,
+    urc: Option<UnboundedReceiver<AtResponse>>
+}
+impl BlockingModem {
+    /// Start talking to the modem at a specified file path.
+    pub fn new_from_path<P: AsRef<Path>>(path: P) -> HuaweiResult<Self> {
+        let core = Core::new()?;
+        let modem = HuaweiModem::new_from_path(path, &core.handle())?;
+        Ok(Self::new(core, modem))
+    }
+    /// Start talking to the modem at a specified file path, applying the given serial line
+    /// settings to it first (see `HuaweiModem::new_from_path_with_config`).
+    pub fn new_from_path_with_config<P: AsRef<Path>>(path: P, config: &SerialConfig) -> HuaweiResult<Self> {
+        let core = Core::new()?;
+        let modem = HuaweiModem::new_from_path_with_config(path, config, &core.handle())?;
+        Ok(Self::new(core, modem))
+    }
+    /// Start talking to a modem over an arbitrary asynchronous byte stream.
+    pub fn new_from_stream<S>(stream: S) -> HuaweiResult<Self> where S: AsyncRead + AsyncWrite + 'static {
+        let core = Core::new()?;
+        let modem = HuaweiModem::new_from_stream(stream, &core.handle())?;
+        Ok(Self::new(core, modem))
+    }
+    /// Start talking to the modem at a specified file path, applying the given serial line
+    /// settings and running the given initialisation sequence (see
+    /// `HuaweiModem::new_from_stream_with_init`).
+    pub fn new_from_path_with_init<P: AsRef<Path>>(path: P, config: &SerialConfig, init: Vec<AtCommand>) -> HuaweiResult<(Self, Vec<InitStepResult>)> {
+        let mut core = Core::new()?;
+        let fut = HuaweiModem::new_from_path_with_init(path, config, init, &core.handle());
+        let (modem, results) = core.run(fut)?;
+        Ok((Self::new(core, modem), results))
+    }
+    fn new(core: Core, modem: HuaweiModem) -> Self {
+        Self { core, modem, urc: None }
+    }
+    /// Run an arbitrary future (e.g. one from the `cmd` module) on this modem's reactor, blocking
+    /// until it completes.
+    ///
+    /// The closure gets access to the underlying `HuaweiModem`, for making said future.
+    pub fn run<F, R>(&mut self, func: F) -> Result<R::Item, R::Error> where F: FnOnce(&HuaweiModem) -> R, R: Future {
+        let fut = func(&self.modem);
+        self.core.run(fut)
+    }
+    /// Send a raw AT command to the modem, and wait for the response.
+    pub fn send_raw(&mut self, cmd: AtCommand) -> HuaweiResult<AtResponsePacket> {
+        self.core.run(self.modem.send_raw(cmd))
+    }
+    /// Run a typed command (see `cmd::AtCommandSpec`), and wait for its parsed response.
+    pub fn execute<C>(&mut self, cmd: C) -> HuaweiResult<C::Output> where C: AtCommandSpec + Send + 'static {
+        self.core.run(self.modem.execute(cmd))
+    }
+    /// Wait for the next URC (Unsolicited Result Code) from the modem.
+    ///
+    /// Until the first time this (or `urcs`) is called, only the most recent 64 URCs are kept, so
+    /// that they don't pile up if you never look at them.
+    ///
+    /// Waits forever if `timeout` is `None`; otherwise, returns `Ok(None)` if nothing arrives
+    /// within the timeout. Returns `HuaweiError::FutureDied` if the connection to the modem has
+    /// gone away.
+    pub fn next_urc(&mut self, timeout: Option<Duration>) -> HuaweiResult<Option<AtResponse>> {
+        let rx = match self.urc.take() {
+            Some(rx) => rx,
+            None => self.modem.take_urc_rx().ok_or(HuaweiError::FutureDied)?
+        };
+        let next = rx.into_future();
+        let res = match timeout {
+            Some(t) => {
+                match self.core.run(next.select2(Delay::new(t))) {
+                    Ok(Either::A(((item, rx), _))) => {
+                        self.urc = Some(rx);
+                        item
+                    },
+                    Ok(Either::B((_, next))) => {
+                        self.urc = next.into_inner();
+                        return Ok(None);
+                    },
+                    Err(Either::A(_)) => return Err(HuaweiError::FutureDied),
+                    Err(Either::B((e, _))) => return Err(e.into())
+                }
+            },
+            None => {
+                let (item, rx) = self.core.run(next)
+                    .map_err(|_| HuaweiError::FutureDied)?;
+                self.urc = Some(rx);
+                item
+            }
+        };
+        match res {
+            Some(r) => Ok(Some(r)),
+            None => Err(HuaweiError::FutureDied)
+        }
+    }
+    /// Returns an iterator that blocks waiting for URCs from the modem, ending when the
+    /// connection to the modem goes away.
+    pub fn urcs(&mut self) -> Urcs<'_> {
+        Urcs { modem: self }
+    }
+    /// Find out which commands the modem supports (see `HuaweiModem::probe_capabilities`).
+    pub fn probe_capabilities(&mut self) -> HuaweiResult<ModemCapabilities> {
+        self.core.run(self.modem.probe_capabilities())
+    }
+    /// Set the TE character set used for strings (`AT+CSCS`).
+    pub fn set_charset(&mut self, charset: Charset) -> HuaweiResult<()> {
+        self.core.run(cmd::general::set_charset(&self.modem, charset))
+    }
+    /// Get the TE character set currently in use (`AT+CSCS`).
+    pub fn get_charset(&mut self) -> HuaweiResult<Charset> {
+        self.core.run(cmd::general::get_charset(&self.modem))
+    }
+    /// Get the modem's current registration state (`AT+CREG`).
+    pub fn get_registration(&mut self) -> HuaweiResult<RegistrationState> {
+        self.core.run(cmd::network::get_registration(&self.modem))
+    }
+    /// Get the modem's current operation mode (`AT+CFUN`).
+    pub fn get_operation_mode(&mut self) -> HuaweiResult<ModemOperationMode> {
+        self.core.run(cmd::network::get_operation_mode(&self.modem))
+    }
+    /// Get the modem's current PIN state (`AT+CPIN`).
+    pub fn get_pin_state(&mut self) -> HuaweiResult<PinState> {
+        self.core.run(cmd::network::get_pin_state(&self.modem))
+    }
+    /// Input the given `pin`, in order to unlock a locked PIN.
+    pub fn input_pin(&mut self, pin: String) -> HuaweiResult<()> {
+        self.core.run(cmd::network::input_pin(&self.modem, pin))
+    }
+    /// Get the modem's current signal quality (`AT+CSQ`).
+    pub fn get_signal_quality(&mut self) -> HuaweiResult<SignalQuality> {
+        self.core.run(cmd::network::get_signal_quality(&self.modem))
+    }
+    /// Get extended signal quality information from a Huawei modem (`AT^HCSQ?`).
+    pub fn get_huawei_signal_quality(&mut self) -> HuaweiResult<HuaweiSignalQuality> {
+        self.core.run(cmd::network::get_huawei_signal_quality(&self.modem))
+    }
+    /// Get extended signal quality information (`AT+CESQ`).
+    pub fn get_extended_signal_quality(&mut self) -> HuaweiResult<ExtendedSignalQuality> {
+        self.core.run(cmd::network::get_extended_signal_quality(&self.modem))
+    }
+    /// Get system information from a Huawei modem (`AT^SYSINFO`).
+    pub fn get_system_info(&mut self) -> HuaweiResult<SystemInfo> {
+        self.core.run(cmd::network::get_system_info(&self.modem))
+    }
+    /// Get extended system information from a Huawei modem (`AT^SYSINFOEX`).
+    pub fn get_extended_system_info(&mut self) -> HuaweiResult<ExtendedSystemInfo> {
+        self.core.run(cmd::network::get_extended_system_info(&self.modem))
+    }
+    /// Get the operator the modem is currently using (`AT+COPS?`).
+    pub fn get_operator(&mut self) -> HuaweiResult<CurrentOperator> {
+        self.core.run(cmd::network::get_operator(&self.modem))
+    }
+    /// Scan for available operators (`AT+COPS=?`).
+    pub fn scan_operators(&mut self) -> HuaweiResult<Vec<NetworkOperator>> {
+        self.core.run(cmd::network::scan_operators(&self.modem))
+    }
+    /// Choose which operator to use (`AT+COPS=<mode>`).
+    pub fn select_operator(&mut self, selection: OperatorSelection) -> HuaweiResult<()> {
+        self.core.run(cmd::network::select_operator(&self.modem, selection))
+    }
+    /// Set the format of operator names returned by `get_operator` (`AT+COPS=3,<format>`).
+    pub fn set_operator_format(&mut self, format: OperatorFormat) -> HuaweiResult<()> {
+        self.core.run(cmd::network::set_operator_format(&self.modem, format))
+    }
+    /// Controls whether to send new message indications to the TE (`AT+CNMI`).
+    ///
+    /// See `cmd::sms::set_new_message_indications`.
+    pub fn set_new_message_indications(&mut self, mode: NewMessageNotification, mt: NewMessageStorage) -> HuaweiResult<()> {
+        self.core.run(cmd::sms::set_new_message_indications(&self.modem, mode, mt))
+    }
+    /// Set the address of the SMS Service Center (`AT+CSCA`).
+    pub fn set_smsc_addr(&mut self, sca: String, tosca: Option<AddressType>) -> HuaweiResult<()> {
+        self.core.run(cmd::sms::set_smsc_addr(&self.modem, sca, tosca))
+    }
+    /// Delete a message from the modem's message store (`AT+CMGD`).
+    pub fn del_sms_pdu(&mut self, del: DeletionOptions) -> HuaweiResult<()> {
+        self.core.run(cmd::sms::del_sms_pdu(&self.modem, del))
+    }
+    /// List SMSes from the modem's message store, in PDU mode (`AT+CMGL`).
+    pub fn list_sms_pdu(&mut self, status: MessageStatus) -> HuaweiResult<Vec<SmsMessage>> {
+        self.core.run(cmd::sms::list_sms_pdu(&self.modem, status))
+    }
+    /// Set whether the modem will use text mode or not (`AT+CMGF`).
+    pub fn set_sms_textmode(&mut self, text: bool) -> HuaweiResult<()> {
+        self.core.run(cmd::sms::set_sms_textmode(&self.modem, text))
+    }
+    /// Send a message to a phone number, in text mode (`AT+CMGS`).
+    pub fn send_sms_textmode(&mut self, to: String, msg: String) -> HuaweiResult<u32> {
+        self.core.run(cmd::sms::send_sms_textmode(&self.modem, to, msg))
+    }
+    /// Send a message to a phone number, in PDU mode (`AT+CMGS`).
+    pub fn send_sms_pdu(&mut self, pdu: &Pdu) -> HuaweiResult<u32> {
+        self.core.run(cmd::sms::send_sms_pdu(&self.modem, pdu))
+    }
+}
+/// Blocking iterator over URCs, returned from `BlockingModem::urcs`.
+pub struct Urcs<'a> {
+    modem: &'a mut BlockingModem
+}
+impl<'a> Iterator for Urcs<'a> {
+    type Item = AtResponse;
+
+    fn next(&mut self) -> Option<AtResponse> {
+        self.modem.next_urc(None).ok().and_then(|x| x)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulator::ModemSimulator;
+
+    #[test]
+    fn blocking_commands_and_urcs() {
+        let sim = ModemSimulator::new();
+        let mut modem = BlockingModem::new_from_stream(sim.stream()).unwrap();
+        assert_eq!(modem.get_registration().unwrap(), RegistrationState::RegisteredHome);
+        assert_eq!(modem.next_urc(Some(Duration::from_millis(10))).unwrap(), None);
+        sim.inject_urc("RING");
+        let urc = modem.urcs().next().unwrap();
+        assert_eq!(urc, AtResponse::ResultCode(crate::at::AtResultCode::Ring));
+    }
+}
@@ -0,0 +1,217 @@
+//! Finding out which commands a modem supports.
+//!
+//! Different modems (and different firmware versions of the same modem) support wildly different
+//! sets of commands. `HuaweiModem::probe_capabilities` asks the modem for a list of the commands
+//! it supports (with `AT+CLAC`), and runs the test forms (`AT<command>=?`) of a few commands to
+//! find out which values they accept. The result is a `ModemCapabilities`, which gets cached on
+//! the `HuaweiModem`, so you can check whether something is supported before trying it:
+//!
+//! ```rust,no_run
+//! # use huawei_modem::HuaweiModem;
+//! # fn f(modem: &HuaweiModem) {
+//! if let Some(caps) = modem.capabilities() {
+//!     if caps.supports("^HCSQ") == Some(true) {
+//!         // ...
+//!     }
+//! }
+//! # }
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use futures::{Future, Stream};
+use futures::stream;
+use crate::{HuaweiModem, CommandFuture};
+use crate::at::{AtCommand, AtResponse, AtResponsePacket, AtValue};
+use crate::charset::Charset;
+use crate::cmd::AtCommandSpec;
+use crate::convert::FromAtValue;
+use crate::errors::{HuaweiError, HuaweiResult};
+
+/// The commands `HuaweiModem::probe_capabilities` runs the test forms of.
+pub const DEFAULT_TEST_COMMANDS: &[&str] = &[
+    "+CFUN", "+CREG", "+CSCS", "+CMGF", "+CNMI", "+CPMS", "+CMGL", "+CMGD"
+];
+
+/// The values a command accepts for one of its parameters (from a test command, like
+/// `+CNMI: (0-2),(0-3)`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupportedValues {
+    /// Supported integer values, as inclusive ranges (a single value `n` is stored as `(n, n)`).
+    pub ranges: Vec<(u32, u32)>,
+    /// Supported string values.
+    pub strings: Vec<String>
+}
+impl SupportedValues {
+    /// Returns `true` if `val` is one of the supported values.
+    pub fn contains_integer(&self, val: u32) -> bool {
+        self.ranges.iter().any(|&(a, b)| a <= val && val <= b)
+    }
+    /// Returns `true` if `val` is one of the supported values (case-insensitively).
+    pub fn contains_string(&self, val: &str) -> bool {
+        self.strings.iter().any(|s| s.eq_ignore_ascii_case(val))
+    }
+}
+/// Accepts a bracketed array of integers, ranges and strings (or just one of them).
+impl FromAtValue for SupportedValues {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        let vals = match *value {
+            AtValue::BracketedArray(ref vals) => vals as &[AtValue],
+            AtValue::Empty => &[],
+            ref v => ::std::slice::from_ref(v)
+        };
+        let mut ret = SupportedValues::default();
+        for v in vals {
+            match *v {
+                AtValue::Integer(i) => ret.ranges.push((i, i)),
+                AtValue::Range(r) => ret.ranges.push(r),
+                AtValue::String(ref s) => ret.strings.push(s.clone()),
+                AtValue::Empty => {},
+                _ => return Err(HuaweiError::TypeMismatch)
+            }
+        }
+        Ok(ret)
+    }
+}
+/// What a modem supports (see the module-level documentation).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModemCapabilities {
+    /// The commands listed by `AT+CLAC` (in upper case, without the `AT` prefix, e.g. `+CSQ` or
+    /// `^HCSQ`), or `None` if the modem doesn't support `AT+CLAC`.
+    pub commands: Option<BTreeSet<String>>,
+    /// The results of the test commands that succeeded, by command name: the supported values
+    /// of each parameter, in order.
+    pub parameters: BTreeMap<String, Vec<SupportedValues>>
+}
+impl ModemCapabilities {
+    /// Check whether the modem supports `command` (e.g. `+CSQ` or `^HCSQ`).
+    ///
+    /// Returns `None` if we don't know (i.e. `AT+CLAC` isn't supported, and we didn't run the
+    /// command's test form).
+    pub fn supports(&self, command: &str) -> Option<bool> {
+        let command = command.to_uppercase();
+        if self.parameters.contains_key(&command) {
+            return Some(true);
+        }
+        self.commands.as_ref().map(|c| c.contains(&command))
+    }
+    /// Get the values supported for parameter `idx` (starting from 0) of `command`, if its test
+    /// form was run.
+    pub fn parameter(&self, command: &str, idx: usize) -> Option<&SupportedValues> {
+        self.parameters.get(&command.to_uppercase())
+            .and_then(|p| p.get(idx))
+    }
+    /// Get the character sets supported by `AT+CSCS`, if its test form was run.
+    pub fn charsets(&self) -> Option<Vec<Charset>> {
+        self.parameter("+CSCS", 0)
+            .map(|p| p.strings.iter().filter_map(|s| Charset::from_name(s)).collect())
+    }
+}
+/// List the commands the modem supports (`AT+CLAC`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ListCommands;
+impl AtCommandSpec for ListCommands {
+    type Output = BTreeSet<String>;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Execute { command: "+CLAC".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<BTreeSet<String>> {
+        pkt.assert_ok()?;
+        let ret = pkt.responses.iter()
+            .filter_map(|r| match *r {
+                AtResponse::Unknown(ref st) => Some(st),
+                _ => None
+            })
+            .map(|st| {
+                let st = st.trim().to_uppercase();
+                match st.get(..2) {
+                    Some("AT") => st[2..].to_string(),
+                    _ => st
+                }
+            })
+            .filter(|st| !st.is_empty())
+            .collect();
+        Ok(ret)
+    }
+}
+/// Run the test form of a command (`AT<command>=?`), returning the values supported for each of
+/// its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCommand(pub String);
+impl AtCommandSpec for TestCommand {
+    type Output = Vec<SupportedValues>;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Test { param: self.0.clone() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<Vec<SupportedValues>> {
+        let val = pkt.extract_named_response(&self.0)?;
+        let params = match *val {
+            AtValue::Array(ref vals) => vals as &[AtValue],
+            ref v => ::std::slice::from_ref(v)
+        };
+        params.iter().map(SupportedValues::from_at_value).collect()
+    }
+}
+/// Run `AT+CLAC` and the test forms of `tests`, ignoring anything the modem says it doesn't
+/// support.
+pub(crate) fn probe(modem: &HuaweiModem, tests: Vec<String>) -> CommandFuture<ModemCapabilities> {
+    let m = modem.clone();
+    let fut = modem.execute(ListCommands)
+        .then(|res| match res {
+            Ok(cmds) => Ok(Some(cmds)),
+            Err(HuaweiError::AtError(e)) => {
+                debug!("modem doesn't support AT+CLAC: {}", e);
+                Ok(None)
+            },
+            Err(e) => Err(e)
+        })
+        .and_then(move |commands| {
+            stream::iter_ok(tests)
+                .and_then(move |name| {
+                    m.execute(TestCommand(name.clone()))
+                        .then(move |res| match res {
+                            Ok(params) => Ok(Some((name, params))),
+                            Err(HuaweiError::AtError(_)) | Err(HuaweiError::ExpectedResponse(_)) => Ok(None),
+                            Err(e) => Err(e)
+                        })
+                })
+                .filter_map(|x| x)
+                .collect()
+                .map(move |params| ModemCapabilities {
+                    commands,
+                    parameters: params.into_iter().collect()
+                })
+        });
+    CommandFuture::new(fut)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_core::reactor::Core;
+    use crate::simulator::ModemSimulator;
+
+    #[test]
+    fn probing() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        assert!(modem.capabilities().is_none());
+        sim.script("AT+CLAC", &["AT+CSQ", "+CNMI", "^HCSQ", "OK"]);
+        sim.script("AT+CNMI=?", &["+CNMI: (0-2),(0,1,3),(0),(0-2),(0,1)", "OK"]);
+        sim.script("AT+CSCS=?", &["+CSCS: (\"IRA\",\"GSM\",\"UCS2\")", "OK"]);
+        let tests = vec!["+CNMI".into(), "+CSCS".into(), "+CUSD".into()];
+        let caps = core.run(modem.probe_capabilities_with(tests)).unwrap();
+        assert_eq!(caps.supports("+csq"), Some(true));
+        assert_eq!(caps.supports("^HCSQ"), Some(true));
+        assert_eq!(caps.supports("+CSCS"), Some(true));
+        assert_eq!(caps.supports("+COPS"), Some(false));
+        let mt = caps.parameter("+CNMI", 1).unwrap();
+        assert!(mt.contains_integer(3) && !mt.contains_integer(2));
+        assert_eq!(caps.charsets(), Some(vec![Charset::Ira, Charset::Gsm, Charset::Ucs2]));
+        assert_eq!(modem.capabilities(), Some(caps));
+
+        sim.script("AT+CLAC", &["ERROR"]);
+        let caps = core.run(modem.probe_capabilities_with(vec![])).unwrap();
+        assert_eq!(caps.supports("+CSQ"), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Character sets used for strings exchanged with the modem (`AT+CSCS`).
+//!
+//! The TE character set, set with `AT+CSCS`, controls how the modem encodes quoted strings in
+//! commands and responses - things like operator names, phonebook entries, and USSD messages.
+//!
+//! Internally, the parser decodes everything it gets from the modem as ISO 8859-1 (which can't
+//! fail, and doesn't lose any information); `AtCodec` then decodes quoted strings in responses
+//! that carry text (like operator names) in the current character set. Everything else (parameter
+//! names, unquoted values, other responses' strings, and unrecognised lines) is left as ISO
+//! 8859-1. The same goes for quoted strings in commands (and text-mode SMS payloads) sent to the
+//! modem, which `AtCodec` encodes in the current character set.
+use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
+use std::fmt;
+use crate::gsm_encoding::{gsm_decode_string, try_gsm_encode_char};
+
+/// A TE character set (from `AT+CSCS`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Charset {
+    /// The GSM 7-bit default alphabet (one unpacked septet per byte).
+    Gsm,
+    /// International Reference Alphabet (i.e. ASCII).
+    ///
+    /// This is the default for most modems. Bytes outside the ASCII range (which some modems
+    /// send anyway) are decoded as ISO 8859-1, but only ASCII is ever sent.
+    #[default]
+    Ira,
+    /// UCS-2, with each character sent as four hexadecimal digits.
+    Ucs2,
+    /// ISO 8859-1 (Latin-1).
+    Latin1
+}
+impl Charset {
+    /// The name of this character set, as used in `AT+CSCS`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Charset::Gsm => "GSM",
+            Charset::Ira => "IRA",
+            Charset::Ucs2 => "UCS2",
+            Charset::Latin1 => "8859-1"
+        }
+    }
+    /// Look up a character set by its `AT+CSCS` name (case-insensitively).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let ret = match &name.to_ascii_uppercase() as &str {
+            "GSM" => Charset::Gsm,
+            "IRA" => Charset::Ira,
+            "UCS2" => Charset::Ucs2,
+            "8859-1" => Charset::Latin1,
+            _ => return None
+        };
+        Some(ret)
+    }
+    /// Decode a string received from the modem in this character set.
+    ///
+    /// This never fails: anything that can't be decoded is replaced with U+FFFD (or, for UCS2
+    /// strings that aren't valid hex, left as ISO 8859-1, since some modems don't bother encoding
+    /// things like storage names).
+    pub fn decode(&self, data: &[u8]) -> String {
+        match *self {
+            Charset::Gsm => gsm_decode_string(data),
+            Charset::Ira | Charset::Latin1 => latin1_decode(data),
+            Charset::Ucs2 => match ucs2_decode(data) {
+                Some(s) => s,
+                None => latin1_decode(data)
+            }
+        }
+    }
+    /// Encode a string to send to the modem in this character set.
+    ///
+    /// Characters that can't be represented in this character set are replaced with `?`.
+    pub fn encode(&self, st: &str) -> Vec<u8> {
+        match *self {
+            Charset::Gsm => {
+                let mut ret = vec![];
+                for c in st.chars() {
+                    if !try_gsm_encode_char(c, &mut ret) {
+                        ret.push(b'?');
+                    }
+                }
+                ret
+            },
+            Charset::Ira => {
+                st.chars()
+                    .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+                    .collect()
+            },
+            Charset::Latin1 => latin1_bytes(st),
+            Charset::Ucs2 => {
+                st.encode_utf16()
+                    .flat_map(|u| format!("{:04X}", u).into_bytes())
+                    .collect()
+            }
+        }
+    }
+}
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+/// Decode ISO 8859-1 bytes, which maps each byte to the Unicode code point with the same value.
+pub(crate) fn latin1_decode(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+/// Recover the bytes of a string made by `latin1_decode` (replacing anything that isn't ISO
+/// 8859-1 with `?`).
+pub(crate) fn latin1_bytes(st: &str) -> Vec<u8> {
+    st.chars()
+        .map(|c| if (c as u32) <= 0xFF { c as u32 as u8 } else { b'?' })
+        .collect()
+}
+fn ucs2_decode(data: &[u8]) -> Option<String> {
+    let chunks = data.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    let units = chunks
+        .map(|c| {
+            ::std::str::from_utf8(c).ok()
+                .and_then(|c| u16::from_str_radix(c, 16).ok())
+        })
+        .collect::<Option<Vec<u16>>>()?;
+    Some(decode_utf16(units).map(|r| r.unwrap_or(REPLACEMENT_CHARACTER)).collect())
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decoding() {
+        assert_eq!(Charset::Ira.decode(b"Op\xE9ra"), "Opéra");
+        assert_eq!(Charset::Latin1.decode(b"Op\xE9ra"), "Opéra");
+        assert_eq!(Charset::Ucs2.decode(b"004800690021"), "Hi!");
+        assert_eq!(Charset::Ucs2.decode(b"D83DDE00"), "\u{1F600}");
+        assert_eq!(Charset::Ucs2.decode(b"SM"), "SM");
+        assert_eq!(Charset::Gsm.decode(b"A\x00\x11"), "A@_");
+        assert_eq!(Charset::Ucs2.encode("Hi\u{1F600}"), b"00480069D83DDE00");
+        assert_eq!(Charset::Gsm.encode("@_\u{3A9}\u{263A}"), b"\x00\x11\x15?");
+        assert_eq!(Charset::Ira.encode("Op\u{E9}ra\u{263A}"), b"Op?ra?");
+        assert_eq!(Charset::Latin1.encode("Op\u{E9}ra\u{263A}"), b"Op\xE9ra?");
+        assert_eq!(Charset::from_name("ucs2"), Some(Charset::Ucs2));
+        assert_eq!(Charset::from_name("8859-1"), Some(Charset::Latin1));
+        assert_eq!(Charset::from_name("KOI8"), None);
+    }
+}
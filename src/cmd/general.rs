@@ -0,0 +1,49 @@
+//! Functions for general modem settings, like the character set used for strings.
+use crate::{HuaweiModem, CommandFuture};
+use crate::at::*;
+use crate::charset::Charset;
+use crate::cmd::AtCommandSpec;
+use crate::errors::*;
+
+/// Set the TE character set used for strings sent to and from the modem (`AT+CSCS`).
+///
+/// Once the modem accepts the change, the library automatically encodes and decodes strings in
+/// the new character set (see the `charset` module).
+pub fn set_charset(modem: &HuaweiModem, charset: Charset) -> CommandFuture<()> {
+    modem.execute(SetCharset(charset))
+}
+/// Set the TE character set (`AT+CSCS`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SetCharset(pub Charset);
+impl AtCommandSpec for SetCharset {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+CSCS".into(),
+            value: AtValue::String(self.0.name().into())
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
+}
+/// Get the TE character set currently in use (`AT+CSCS`).
+pub fn get_charset(modem: &HuaweiModem) -> CommandFuture<Charset> {
+    modem.execute(GetCharset)
+}
+/// Get the TE character set currently in use (`AT+CSCS`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetCharset;
+impl AtCommandSpec for GetCharset {
+    type Output = Charset;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "+CSCS".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<Charset> {
+        let name: String = pkt.decode_named_response("+CSCS")?;
+        Charset::from_name(&name)
+            .ok_or(HuaweiError::ValueOutOfRange(AtValue::String(name)))
+    }
+}
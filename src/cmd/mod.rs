@@ -4,5 +4,85 @@
 //! something with the modem, and will return some form of typed result. Basically, you probably
 //! want to take a look in here if you want to do anything useful without having to have a copy of
 //! the modem manual yourself!
+//!
+//! Each command is described by a type implementing `AtCommandSpec`, which says what to send to
+//! the modem and how to parse its response; `HuaweiModem::execute` runs one. The functions in the
+//! submodules are just shorthand for executing the command types next to them. If you need a
+//! command this library doesn't wrap, you can implement `AtCommandSpec` yourself:
+//!
+//! ```rust,no_run
+//! use huawei_modem::{HuaweiModem, HuaweiResult};
+//! use huawei_modem::at::{AtCommand, AtResponsePacket};
+//! use huawei_modem::cmd::AtCommandSpec;
+//!
+//! /// Get the modem's IMEI (`AT+CGSN`).
+//! struct GetImei;
+//! impl AtCommandSpec for GetImei {
+//!     type Output = String;
+//!
+//!     fn command(&self) -> AtCommand {
+//!         AtCommand::Execute { command: "+CGSN".into() }
+//!     }
+//!     fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<String> {
+//!         pkt.decode_named_response("+CGSN")
+//!     }
+//! }
+//! # fn f(modem: &HuaweiModem) {
+//! let imei = modem.execute(GetImei);
+//! # }
+//! ```
+use std::time::Duration;
+use crate::HuaweiResult;
+use crate::errors::HuaweiError;
+use crate::at::{AtCommand, AtResponsePacket};
+use crate::queue::Priority;
+
+pub mod general;
 pub mod network;
 pub mod sms;
+
+/// A typed AT command: what to send to the modem, and how to parse the response.
+pub trait AtCommandSpec {
+    /// The type of the parsed response.
+    type Output: Send + 'static;
+
+    /// The command to send.
+    fn command(&self) -> AtCommand;
+    /// The names of the information responses the command returns (all other information
+    /// responses are treated as URCs).
+    ///
+    /// Defaults to `self.command().expected()`.
+    fn expected(&self) -> Vec<String> {
+        self.command().expected()
+    }
+    /// How long to wait for the command to complete.
+    ///
+    /// Defaults to `self.command().default_timeout()`.
+    fn timeout(&self) -> Duration {
+        self.command().default_timeout()
+    }
+    /// Check that the command's arguments are valid, before it gets sent.
+    ///
+    /// `HuaweiModem::execute` fails with the returned error, without sending anything, if this
+    /// fails. (The command itself is always checked with `AtCommand::validate`, too.)
+    fn validate(&self) -> HuaweiResult<()> {
+        Ok(())
+    }
+    /// The priority of the command in the request queue.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+    /// Parse the modem's response to the command.
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<Self::Output>;
+}
+/// Check that `num` looks like a phone number (an optional `+`, followed by digits, `*` or `#`).
+pub(crate) fn validate_number(num: &str) -> HuaweiResult<()> {
+    let digits = num.strip_prefix('+').unwrap_or(num);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == '*' || c == '#') {
+        return Err(HuaweiError::UnsafeValue {
+            value: num.into(),
+            reason: "not a valid phone number"
+        });
+    }
+    Ok(())
+}
@@ -1,9 +1,11 @@
 //! Functions for dealing with registration on a GSM network (signal quality, PIN, etc.)
-use crate::{HuaweiModem};
+use std::fmt;
+use num::FromPrimitive;
+use crate::{HuaweiModem, CommandFuture};
 use crate::at::*;
+use crate::cmd::AtCommandSpec;
+use crate::convert::{FromAtValue, field, fields};
 use crate::errors::*;
-use futures::Future;
-use crate::util::HuaweiFromPrimitive;
 
 /// The current registration state of the modem (from `AT+CREG`).
 /// 
@@ -11,7 +13,7 @@ use crate::util::HuaweiFromPrimitive;
 /// anything useful (text, call, etc.). Therefore, checking the registration state can be useful to
 /// figure out why your modem isn't working.
 #[repr(u8)]
-#[derive(Fail, Debug, FromPrimitive, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RegistrationState {
     /// Not registered, and not searching for a new operator at present.
     #[fail(display = "Not registered; not searching for a new operator")]
@@ -52,7 +54,7 @@ impl RegistrationState {
 /// online is not allowed on some modems, presumably requiring a restart. Consulting your modem
 /// manual may be advisable.
 #[repr(u8)]
-#[derive(Fail, Debug, FromPrimitive, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ModemOperationMode {
     /// Minimum functionality possible.
     ///
@@ -77,6 +79,52 @@ pub enum ModemOperationMode {
     #[fail(display = "Disable RF")]
     DisableRf = 7
 }
+/// The network service status of the modem (from `^SRVST` URCs, and `AT^SYSINFO`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ServiceStatus {
+    /// No service.
+    #[fail(display = "No service")]
+    NoService = 0,
+    /// Restricted service (e.g. emergency calls only).
+    #[fail(display = "Restricted service")]
+    Restricted = 1,
+    /// Valid service.
+    #[fail(display = "Valid service")]
+    Valid = 2,
+    /// Restricted regional service.
+    #[fail(display = "Restricted regional service")]
+    RestrictedRegional = 3,
+    /// Power saving or deep sleep state.
+    #[fail(display = "Power saving or deep sleep")]
+    PowerSaving = 4
+}
+/// The state of the modem's SIM card (from `^SIMST` URCs, and `AT^SYSINFO`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimState {
+    /// Invalid SIM card, or PIN not yet entered.
+    #[fail(display = "Invalid SIM or SIM locked")]
+    Invalid = 0,
+    /// Valid SIM card.
+    #[fail(display = "Valid SIM")]
+    Valid = 1,
+    /// SIM card invalid for circuit-switched (CS) services.
+    #[fail(display = "SIM invalid for CS services")]
+    InvalidForCs = 2,
+    /// SIM card invalid for packet-switched (PS) services.
+    #[fail(display = "SIM invalid for PS services")]
+    InvalidForPs = 3,
+    /// SIM card invalid for both CS and PS services.
+    #[fail(display = "SIM invalid for CS and PS services")]
+    InvalidForCsAndPs = 4,
+    /// ROM SIM version.
+    #[fail(display = "ROM SIM")]
+    RomSim = 240,
+    /// No SIM card present.
+    #[fail(display = "No SIM present")]
+    NotPresent = 255
+}
 /// The PIN state of the modem (from `AT+CPIN`).
 ///
 /// If the SIM is locked with a PIN, you must enter it before using the modem.
@@ -115,52 +163,100 @@ impl PinState {
         Ok(r)
     }
 }
+impl FromAtValue for PinState {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        PinState::from_string(value.get_unknown()?)
+    }
+}
 /// Get the modem's current registration state (`AT+CREG`).
-pub fn get_registration(modem: &mut HuaweiModem) -> impl Future<Item = RegistrationState, Error = HuaweiError> {
-    modem.send_raw(AtCommand::Read { param: "+CREG".into() })
-        .and_then(|pkt| {
-            let reg = pkt.extract_named_response("+CREG")?
-                .get_array()?
-                .get(1)
-                .ok_or(HuaweiError::TypeMismatch)?
-                .get_integer()?;
-            let regst = RegistrationState::from_integer(*reg)?;
-            Ok(regst)
-        })
+pub fn get_registration(modem: &HuaweiModem) -> CommandFuture<RegistrationState> {
+    modem.execute(GetRegistration)
+}
+/// Get the modem's current registration state (`AT+CREG`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetRegistration;
+impl AtCommandSpec for GetRegistration {
+    type Output = RegistrationState;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "+CREG".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<RegistrationState> {
+        let (_, regst): (u32, RegistrationState) = pkt.decode_named_response("+CREG")?;
+        Ok(regst)
+    }
 }
 /// Get the modem's current operation mode (`AT+CFUN`).
-pub fn get_operation_mode(modem: &mut HuaweiModem) -> impl Future<Item = ModemOperationMode, Error = HuaweiError> {
-    modem.send_raw(AtCommand::Read { param: "+CFUN".into() })
-        .and_then(|pkt| {
-            let rpl = pkt.extract_named_response("+CFUN")?
-                .get_integer()?;
-            Ok(ModemOperationMode::from_integer(*rpl)?)
-        })
+pub fn get_operation_mode(modem: &HuaweiModem) -> CommandFuture<ModemOperationMode> {
+    modem.execute(GetOperationMode)
+}
+/// Get the modem's current operation mode (`AT+CFUN`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetOperationMode;
+impl AtCommandSpec for GetOperationMode {
+    type Output = ModemOperationMode;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "+CFUN".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<ModemOperationMode> {
+        pkt.decode_named_response("+CFUN")
+    }
 }
 /// Get the modem's current PIN state (`AT+CPIN`).
-pub fn get_pin_state(modem: &mut HuaweiModem) -> impl Future<Item = PinState, Error = HuaweiError> {
-    modem.send_raw(AtCommand::Read { param: "+CPIN".into() })
-        .and_then(|pkt| {
-            let rpl = pkt.extract_named_response("+CPIN")?
-                .get_unknown()?;
-            Ok(PinState::from_string(rpl)?)
-        })
+pub fn get_pin_state(modem: &HuaweiModem) -> CommandFuture<PinState> {
+    modem.execute(GetPinState)
+}
+/// Get the modem's current PIN state (`AT+CPIN`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetPinState;
+impl AtCommandSpec for GetPinState {
+    type Output = PinState;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "+CPIN".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<PinState> {
+        pkt.decode_named_response("+CPIN")
+    }
 }
 /// Input the given `pin`, in order to unlock a locked PIN.
-pub fn input_pin(modem: &mut HuaweiModem, pin: String) -> impl Future<Item = (), Error = HuaweiError> {
-    modem.send_raw(AtCommand::Equals { 
-        param: "+CPIN".into(),
-        value: AtValue::String(pin)
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
+pub fn input_pin(modem: &HuaweiModem, pin: String) -> CommandFuture<()> {
+    modem.execute(InputPin { pin })
+}
+/// Input the given `pin`, in order to unlock a locked PIN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPin {
+    /// The PIN to enter.
+    pub pin: String
+}
+impl AtCommandSpec for InputPin {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+CPIN".into(),
+            value: AtValue::String(self.pin.clone())
+        }
+    }
+    fn validate(&self) -> HuaweiResult<()> {
+        if self.pin.is_empty() || !self.pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err(HuaweiError::UnsafeValue {
+                value: self.pin.clone(),
+                reason: "PINs can only contain digits"
+            });
+        }
         Ok(())
-    })
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
 }
 /// Signal quality, as returned from the modem (`AT+CSQ`).
 ///
 /// The exact values of this `struct` may vary based on your modem type. Consult your modem manual
 /// for more information.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, FromAtValue, Copy, Clone, PartialEq, Eq)]
 pub struct SignalQuality {
     /// Recieved Signal Strength Indication (RSSI) value.
     ///
@@ -177,18 +273,944 @@ pub struct SignalQuality {
     /// On some modems, this is permanently 99 (i.e. unsupported).
     pub ber: u32
 }
+impl SignalQuality {
+    /// Convert `rssi` to dBm, or `None` if it's unknown.
+    ///
+    /// 0 and 31 are returned as -113 and -51 dBm, although they really mean "this or less" and
+    /// "this or more".
+    pub fn rssi_dbm(&self) -> Option<i32> {
+        index_to_dbm(self.rssi, 31, -111, 2)
+    }
+}
+/// Convert an index from a signal quality command to dBm, where 1 is `start` dBm, each step is
+/// `step` dBm, and anything above `max` is unknown.
+fn index_to_dbm(idx: u32, max: u32, start: i32, step: i32) -> Option<i32> {
+    if idx <= max {
+        Some(start + (idx as i32 - 1) * step)
+    }
+    else {
+        None
+    }
+}
+/// Like `index_to_dbm`, but for values with fractional steps (e.g. dB for Ec/Io or RSRQ).
+fn index_to_db(idx: u32, max: u32, start: f32, step: f32) -> Option<f32> {
+    if idx <= max {
+        Some(start + (idx as f32 - 1.0) * step)
+    }
+    else {
+        None
+    }
+}
+/// Get the modem's current signal quality (`AT+CSQ`).
+pub fn get_signal_quality(modem: &HuaweiModem) -> CommandFuture<SignalQuality> {
+    modem.execute(GetSignalQuality)
+}
 /// Get the modem's current signal quality (`AT+CSQ`).
-pub fn get_signal_quality(modem: &mut HuaweiModem) -> impl Future<Item = SignalQuality, Error = HuaweiError> {
-    modem.send_raw(AtCommand::Execute { command: "+CSQ".into() })
-        .and_then(|pkt| {
-            let rpl = pkt.extract_named_response("+CSQ")?
-                .get_array()?;
-            let rssi = rpl.get(0)
-                .ok_or(HuaweiError::TypeMismatch)?
-                .get_integer()?;
-            let ber = rpl.get(1)
-                .ok_or(HuaweiError::TypeMismatch)?
-                .get_integer()?;
-            Ok(SignalQuality { rssi: *rssi, ber: *ber })
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetSignalQuality;
+impl AtCommandSpec for GetSignalQuality {
+    type Output = SignalQuality;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Execute { command: "+CSQ".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<SignalQuality> {
+        pkt.decode_named_response("+CSQ")
+    }
+}
+/// The radio technology a `HuaweiSignalQuality` was measured on (from `AT^HCSQ`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignalSystemMode {
+    /// No service.
+    NoService,
+    /// GSM (2G).
+    Gsm,
+    /// WCDMA (3G).
+    Wcdma,
+    /// TD-SCDMA (3G).
+    TdScdma,
+    /// LTE (4G).
+    Lte,
+    /// CDMA 1x.
+    Cdma,
+    /// CDMA EV-DO.
+    Evdo,
+    /// Something else, not understood by this library.
+    Other(String)
+}
+impl SignalSystemMode {
+    /// Look up a system mode by the name `AT^HCSQ` uses for it.
+    pub fn from_name(name: &str) -> Self {
+        use self::SignalSystemMode::*;
+
+        match &name.to_ascii_uppercase() as &str {
+            "NOSERVICE" => NoService,
+            "GSM" => Gsm,
+            "WCDMA" => Wcdma,
+            "TD-SCDMA" => TdScdma,
+            "LTE" => Lte,
+            "CDMA" => Cdma,
+            "EVDO" => Evdo,
+            _ => Other(name.into())
+        }
+    }
+}
+impl fmt::Display for SignalSystemMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SignalSystemMode::*;
+
+        match *self {
+            NoService => write!(f, "No service"),
+            Gsm => write!(f, "GSM"),
+            Wcdma => write!(f, "WCDMA"),
+            TdScdma => write!(f, "TD-SCDMA"),
+            Lte => write!(f, "LTE"),
+            Cdma => write!(f, "CDMA"),
+            Evdo => write!(f, "EV-DO"),
+            Other(ref name) => write!(f, "{}", name)
+        }
+    }
+}
+/// Extended signal quality, as returned from Huawei modems (`AT^HCSQ`).
+///
+/// Which of the measurements are present depends on `sysmode`. The values are indices, not
+/// dBm; use the `_dbm` and `_db` methods to convert them (which return `None` if the value is
+/// missing or unknown). Index 0 really means "less than" the value for index 1, and is returned
+/// as one step below it; the highest index really means "this or more".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuaweiSignalQuality {
+    /// The radio technology the measurements are for.
+    pub sysmode: SignalSystemMode,
+    /// Received signal strength (GSM, WCDMA, TD-SCDMA, LTE, CDMA and EV-DO).
+    pub rssi: Option<u32>,
+    /// Received signal code power (WCDMA and TD-SCDMA).
+    pub rscp: Option<u32>,
+    /// Ec/Io, i.e. the signal to noise ratio of the pilot channel (WCDMA, TD-SCDMA, CDMA and
+    /// EV-DO).
+    pub ecio: Option<u32>,
+    /// Reference signal received power (LTE).
+    pub rsrp: Option<u32>,
+    /// Signal to interference plus noise ratio (LTE and EV-DO).
+    pub sinr: Option<u32>,
+    /// Reference signal received quality (LTE).
+    pub rsrq: Option<u32>
+}
+impl HuaweiSignalQuality {
+    /// Convert `rssi` to dBm (from -121, meaning less than -120, to -25 dBm).
+    pub fn rssi_dbm(&self) -> Option<i32> {
+        self.rssi.and_then(|x| index_to_dbm(x, 96, -120, 1))
+    }
+    /// Convert `rscp` to dBm (from -121, meaning less than -120, to -25 dBm).
+    pub fn rscp_dbm(&self) -> Option<i32> {
+        self.rscp.and_then(|x| index_to_dbm(x, 96, -120, 1))
+    }
+    /// Convert `ecio` to dB (from -32.5, meaning less than -32, to 0 dB).
+    pub fn ecio_db(&self) -> Option<f32> {
+        self.ecio.and_then(|x| index_to_db(x, 65, -32.0, 0.5))
+    }
+    /// Convert `rsrp` to dBm (from -141, meaning less than -140, to -44 dBm).
+    pub fn rsrp_dbm(&self) -> Option<i32> {
+        self.rsrp.and_then(|x| index_to_dbm(x, 97, -140, 1))
+    }
+    /// Convert `sinr` to dB (from -20.2, meaning less than -20, to 30 dB).
+    pub fn sinr_db(&self) -> Option<f32> {
+        self.sinr.and_then(|x| index_to_db(x, 251, -20.0, 0.2))
+    }
+    /// Convert `rsrq` to dB (from -20, meaning less than -19.5, to -3 dB).
+    pub fn rsrq_db(&self) -> Option<f32> {
+        self.rsrq.and_then(|x| index_to_db(x, 34, -19.5, 0.5))
+    }
+}
+impl FromAtValue for HuaweiSignalQuality {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        use self::SignalSystemMode::*;
+
+        let f = fields(value);
+        let sysmode = SignalSystemMode::from_name(&field::<String>(f, 0, "HuaweiSignalQuality.sysmode")?);
+        let val = |idx, name| field::<Option<u32>>(f, idx, name);
+        let mut ret = HuaweiSignalQuality {
+            sysmode: sysmode.clone(),
+            rssi: None, rscp: None, ecio: None, rsrp: None, sinr: None, rsrq: None
+        };
+        match sysmode {
+            Gsm => {
+                ret.rssi = val(1, "HuaweiSignalQuality.rssi")?;
+            },
+            Wcdma | TdScdma => {
+                ret.rssi = val(1, "HuaweiSignalQuality.rssi")?;
+                ret.rscp = val(2, "HuaweiSignalQuality.rscp")?;
+                ret.ecio = val(3, "HuaweiSignalQuality.ecio")?;
+            },
+            Lte => {
+                ret.rssi = val(1, "HuaweiSignalQuality.rssi")?;
+                ret.rsrp = val(2, "HuaweiSignalQuality.rsrp")?;
+                ret.sinr = val(3, "HuaweiSignalQuality.sinr")?;
+                ret.rsrq = val(4, "HuaweiSignalQuality.rsrq")?;
+            },
+            Cdma => {
+                ret.rssi = val(1, "HuaweiSignalQuality.rssi")?;
+                ret.ecio = val(2, "HuaweiSignalQuality.ecio")?;
+            },
+            Evdo => {
+                ret.rssi = val(1, "HuaweiSignalQuality.rssi")?;
+                ret.sinr = val(2, "HuaweiSignalQuality.sinr")?;
+                ret.ecio = val(3, "HuaweiSignalQuality.ecio")?;
+            },
+            NoService | Other(_) => {}
+        }
+        Ok(ret)
+    }
+}
+/// Get extended signal quality information from a Huawei modem (`AT^HCSQ?`).
+pub fn get_huawei_signal_quality(modem: &HuaweiModem) -> CommandFuture<HuaweiSignalQuality> {
+    modem.execute(GetHuaweiSignalQuality)
+}
+/// Get extended signal quality information from a Huawei modem (`AT^HCSQ?`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetHuaweiSignalQuality;
+impl AtCommandSpec for GetHuaweiSignalQuality {
+    type Output = HuaweiSignalQuality;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "^HCSQ".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<HuaweiSignalQuality> {
+        pkt.decode_named_response("^HCSQ")
+    }
+}
+/// Extended signal quality, as defined by 3GPP (`AT+CESQ`).
+///
+/// Only the measurements for the radio technology currently in use will be known. As with
+/// `HuaweiSignalQuality`, use the `_dbm` and `_db` methods to convert the values.
+#[derive(Debug, FromAtValue, Copy, Clone, PartialEq, Eq)]
+pub struct ExtendedSignalQuality {
+    /// Received signal strength level (GSM).
+    pub rxlev: u32,
+    /// Channel bit error rate (GSM), from 0 to 7 (99 if unknown).
+    pub ber: u32,
+    /// Received signal code power (UTRAN).
+    pub rscp: u32,
+    /// Ec/No, i.e. the ratio of received energy per chip to noise density (UTRAN).
+    pub ecno: u32,
+    /// Reference signal received quality (E-UTRAN).
+    pub rsrq: u32,
+    /// Reference signal received power (E-UTRAN).
+    pub rsrp: u32
+}
+impl ExtendedSignalQuality {
+    /// Convert `rxlev` to dBm (from -111, meaning less than -110, to -48 dBm).
+    pub fn rxlev_dbm(&self) -> Option<i32> {
+        index_to_dbm(self.rxlev, 63, -110, 1)
+    }
+    /// Convert `rscp` to dBm (from -121, meaning less than -120, to -25 dBm).
+    pub fn rscp_dbm(&self) -> Option<i32> {
+        index_to_dbm(self.rscp, 96, -120, 1)
+    }
+    /// Convert `ecno` to dB (from -24.5, meaning less than -24, to 0 dB).
+    pub fn ecno_db(&self) -> Option<f32> {
+        index_to_db(self.ecno, 49, -24.0, 0.5)
+    }
+    /// Convert `rsrq` to dB (from -20, meaning less than -19.5, to -3 dB).
+    pub fn rsrq_db(&self) -> Option<f32> {
+        index_to_db(self.rsrq, 34, -19.5, 0.5)
+    }
+    /// Convert `rsrp` to dBm (from -141, meaning less than -140, to -44 dBm).
+    pub fn rsrp_dbm(&self) -> Option<i32> {
+        index_to_dbm(self.rsrp, 97, -140, 1)
+    }
+}
+/// Get extended signal quality information (`AT+CESQ`).
+pub fn get_extended_signal_quality(modem: &HuaweiModem) -> CommandFuture<ExtendedSignalQuality> {
+    modem.execute(GetExtendedSignalQuality)
+}
+/// Get extended signal quality information (`AT+CESQ`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetExtendedSignalQuality;
+impl AtCommandSpec for GetExtendedSignalQuality {
+    type Output = ExtendedSignalQuality;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Execute { command: "+CESQ".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<ExtendedSignalQuality> {
+        pkt.decode_named_response("+CESQ")
+    }
+}
+/// How the modem selects which operator (network) to use (from `AT+COPS`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperatorSelectionMode {
+    /// The modem picks an operator automatically.
+    #[fail(display = "Automatic")]
+    Automatic = 0,
+    /// A specific operator was chosen manually.
+    #[fail(display = "Manual")]
+    Manual = 1,
+    /// Deregistered from the network.
+    #[fail(display = "Deregistered")]
+    Deregistered = 2,
+    /// Only used to set the operator name format (see `set_operator_format`).
+    #[fail(display = "Set format only")]
+    SetFormatOnly = 3,
+    /// A specific operator was chosen manually, falling back to automatic selection if it isn't
+    /// available.
+    #[fail(display = "Manual, falling back to automatic")]
+    ManualThenAutomatic = 4
+}
+/// The format of operator names (in `AT+COPS`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperatorFormat {
+    /// The operator's full name (e.g. "Vodafone UK").
+    #[fail(display = "Long alphanumeric")]
+    LongAlphanumeric = 0,
+    /// The operator's abbreviated name (e.g. "voda UK").
+    #[fail(display = "Short alphanumeric")]
+    ShortAlphanumeric = 1,
+    /// The operator's numeric code, i.e. its MCC and MNC (e.g. "23415").
+    #[fail(display = "Numeric")]
+    Numeric = 2
+}
+/// A radio access technology (from `AT+COPS`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessTechnology {
+    /// GSM (2G).
+    #[fail(display = "GSM")]
+    Gsm = 0,
+    /// GSM Compact.
+    #[fail(display = "GSM Compact")]
+    GsmCompact = 1,
+    /// UTRAN (3G).
+    #[fail(display = "UTRAN")]
+    Utran = 2,
+    /// GSM with EGPRS (EDGE).
+    #[fail(display = "GSM w/EGPRS")]
+    GsmEgprs = 3,
+    /// UTRAN with HSDPA.
+    #[fail(display = "UTRAN w/HSDPA")]
+    UtranHsdpa = 4,
+    /// UTRAN with HSUPA.
+    #[fail(display = "UTRAN w/HSUPA")]
+    UtranHsupa = 5,
+    /// UTRAN with HSDPA and HSUPA.
+    #[fail(display = "UTRAN w/HSDPA and HSUPA")]
+    UtranHspa = 6,
+    /// E-UTRAN (LTE, 4G).
+    #[fail(display = "E-UTRAN")]
+    Eutran = 7
+}
+/// The availability of an operator (from `AT+COPS=?`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperatorStatus {
+    /// Unknown.
+    #[fail(display = "Unknown")]
+    Unknown = 0,
+    /// Available to register with.
+    #[fail(display = "Available")]
+    Available = 1,
+    /// Currently registered with.
+    #[fail(display = "Current")]
+    Current = 2,
+    /// Forbidden (i.e. we aren't allowed to register with it).
+    #[fail(display = "Forbidden")]
+    Forbidden = 3
+}
+/// The operator the modem is currently using (from `AT+COPS?`).
+#[derive(Debug, FromAtValue, Clone, PartialEq, Eq)]
+pub struct CurrentOperator {
+    /// How the operator was selected.
+    pub mode: OperatorSelectionMode,
+    /// The format of `operator`.
+    pub format: Option<OperatorFormat>,
+    /// The operator's name or numeric code, depending on `format` (`None` if the modem isn't
+    /// registered).
+    pub operator: Option<String>,
+    /// The access technology in use, if the modem says.
+    pub act: Option<AccessTechnology>
+}
+/// An operator found by a network scan (from `AT+COPS=?`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkOperator {
+    /// Whether the operator is available.
+    pub status: OperatorStatus,
+    /// The operator's full name.
+    pub long_name: Option<String>,
+    /// The operator's abbreviated name.
+    pub short_name: Option<String>,
+    /// The operator's numeric code (MCC and MNC).
+    pub numeric: Option<String>,
+    /// The access technology the operator was found on, if the modem says.
+    pub act: Option<AccessTechnology>
+}
+/// Decodes one of the bracketed tuples returned from `AT+COPS=?`.
+impl FromAtValue for NetworkOperator {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        let f = value.get_bracketed_array()?;
+        Ok(NetworkOperator {
+            status: field(f, 0, "NetworkOperator.status")?,
+            long_name: field(f, 1, "NetworkOperator.long_name")?,
+            short_name: field(f, 2, "NetworkOperator.short_name")?,
+            numeric: field(f, 3, "NetworkOperator.numeric")?,
+            act: field(f, 4, "NetworkOperator.act")?
+        })
+    }
+}
+/// Which operator to use (for `select_operator`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperatorSelection {
+    /// Let the modem pick an operator automatically.
+    Automatic,
+    /// Use a specific operator.
+    Manual {
+        /// The format of `operator`.
+        format: OperatorFormat,
+        /// The operator's name or numeric code, depending on `format`.
+        operator: String,
+        /// The access technology to use, if it matters.
+        act: Option<AccessTechnology>,
+        /// Whether to fall back to automatic selection if the operator isn't available.
+        fallback: bool
+    },
+    /// Deregister from the network.
+    Deregister
+}
+/// Get the operator the modem is currently using (`AT+COPS?`).
+///
+/// The operator is returned in the format last set with `set_operator_format` (by default, most
+/// modems use `OperatorFormat::LongAlphanumeric`).
+pub fn get_operator(modem: &HuaweiModem) -> CommandFuture<CurrentOperator> {
+    modem.execute(GetOperator)
+}
+/// Get the operator the modem is currently using (`AT+COPS?`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetOperator;
+impl AtCommandSpec for GetOperator {
+    type Output = CurrentOperator;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Read { param: "+COPS".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<CurrentOperator> {
+        pkt.decode_named_response("+COPS")
+    }
+}
+/// Scan for available operators (`AT+COPS=?`).
+///
+/// This can take a few minutes, and some modems drop their network registration while it's
+/// running.
+pub fn scan_operators(modem: &HuaweiModem) -> CommandFuture<Vec<NetworkOperator>> {
+    modem.execute(ScanOperators)
+}
+/// Scan for available operators (`AT+COPS=?`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScanOperators;
+impl AtCommandSpec for ScanOperators {
+    type Output = Vec<NetworkOperator>;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Test { param: "+COPS".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<Vec<NetworkOperator>> {
+        let val = pkt.extract_named_response("+COPS")?;
+        // the operators are followed by an empty value, and then the supported modes and formats
+        fields(val).iter()
+            .take_while(|v| !v.is_empty())
+            .map(NetworkOperator::from_at_value)
+            .collect()
+    }
+}
+/// Choose which operator to use (`AT+COPS=<mode>`).
+pub fn select_operator(modem: &HuaweiModem, selection: OperatorSelection) -> CommandFuture<()> {
+    modem.execute(SelectOperator(selection))
+}
+/// Choose which operator to use (`AT+COPS=<mode>`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectOperator(pub OperatorSelection);
+impl AtCommandSpec for SelectOperator {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        let value = match self.0 {
+            OperatorSelection::Automatic => AtValue::Integer(OperatorSelectionMode::Automatic as u32),
+            OperatorSelection::Deregister => AtValue::Integer(OperatorSelectionMode::Deregistered as u32),
+            OperatorSelection::Manual { format, ref operator, act, fallback } => {
+                let mode = if fallback {
+                    OperatorSelectionMode::ManualThenAutomatic
+                }
+                else {
+                    OperatorSelectionMode::Manual
+                };
+                let mut arr = vec![
+                    AtValue::Integer(mode as u32),
+                    AtValue::Integer(format as u32),
+                    AtValue::String(operator.clone())
+                ];
+                if let Some(act) = act {
+                    arr.push(AtValue::Integer(act as u32));
+                }
+                AtValue::Array(arr)
+            }
+        };
+        AtCommand::Equals { param: "+COPS".into(), value }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
+}
+/// Set the format of operator names returned by `get_operator` (`AT+COPS=3,<format>`).
+pub fn set_operator_format(modem: &HuaweiModem, format: OperatorFormat) -> CommandFuture<()> {
+    modem.execute(SetOperatorFormat(format))
+}
+/// Set the format of operator names returned by `get_operator` (`AT+COPS=3,<format>`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SetOperatorFormat(pub OperatorFormat);
+impl AtCommandSpec for SetOperatorFormat {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+COPS".into(),
+            value: AtValue::Array(vec![
+                AtValue::Integer(OperatorSelectionMode::SetFormatOnly as u32),
+                AtValue::Integer(self.0 as u32)
+            ])
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
+}
+/// Which domains the modem has service in (from `AT^SYSINFO`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ServiceDomain {
+    /// No service.
+    #[fail(display = "No service")]
+    NoService = 0,
+    /// Circuit-switched (CS) service only, i.e. calls and SMS but no data.
+    #[fail(display = "CS service only")]
+    CsOnly = 1,
+    /// Packet-switched (PS) service only, i.e. data but no calls.
+    #[fail(display = "PS service only")]
+    PsOnly = 2,
+    /// Both packet-switched (PS) and circuit-switched (CS) service.
+    #[fail(display = "PS and CS service")]
+    PsAndCs = 3,
+    /// Not registered for either CS or PS service, and searching.
+    #[fail(display = "Not registered; searching")]
+    Searching = 4,
+    /// Not supported (on CDMA modems).
+    #[fail(display = "Service domain not supported")]
+    NotSupported = 255
+}
+/// The system mode of the modem (from `AT^SYSINFO` and `^MODE` URCs).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SystemMode {
+    /// No service.
+    #[fail(display = "No service")]
+    NoService = 0,
+    /// AMPS.
+    #[fail(display = "AMPS")]
+    Amps = 1,
+    /// CDMA.
+    #[fail(display = "CDMA")]
+    Cdma = 2,
+    /// GSM/GPRS (2G).
+    #[fail(display = "GSM/GPRS")]
+    GsmGprs = 3,
+    /// HDR (CDMA EV-DO).
+    #[fail(display = "HDR")]
+    Hdr = 4,
+    /// WCDMA (3G).
+    #[fail(display = "WCDMA")]
+    Wcdma = 5,
+    /// GPS.
+    #[fail(display = "GPS")]
+    Gps = 6,
+    /// GSM/WCDMA.
+    #[fail(display = "GSM/WCDMA")]
+    GsmWcdma = 7,
+    /// CDMA/HDR hybrid.
+    #[fail(display = "CDMA/HDR hybrid")]
+    CdmaHdrHybrid = 8,
+    /// TD-SCDMA (3G).
+    #[fail(display = "TD-SCDMA")]
+    TdScdma = 15
+}
+/// The system submode of the modem, i.e. which radio technology it's using in more detail (from
+/// `AT^SYSINFO` and `^MODE` URCs).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SystemSubmode {
+    /// No service.
+    #[fail(display = "No service")]
+    NoService = 0,
+    /// GSM.
+    #[fail(display = "GSM")]
+    Gsm = 1,
+    /// GPRS.
+    #[fail(display = "GPRS")]
+    Gprs = 2,
+    /// EDGE.
+    #[fail(display = "EDGE")]
+    Edge = 3,
+    /// WCDMA.
+    #[fail(display = "WCDMA")]
+    Wcdma = 4,
+    /// HSDPA.
+    #[fail(display = "HSDPA")]
+    Hsdpa = 5,
+    /// HSUPA.
+    #[fail(display = "HSUPA")]
+    Hsupa = 6,
+    /// HSDPA and HSUPA.
+    #[fail(display = "HSDPA and HSUPA")]
+    HsdpaHsupa = 7,
+    /// TD-SCDMA.
+    #[fail(display = "TD-SCDMA")]
+    TdScdma = 8,
+    /// HSPA+.
+    #[fail(display = "HSPA+")]
+    HspaPlus = 9,
+    /// EV-DO Rev. 0.
+    #[fail(display = "EV-DO Rev. 0")]
+    EvdoRev0 = 10,
+    /// EV-DO Rev. A.
+    #[fail(display = "EV-DO Rev. A")]
+    EvdoRevA = 11,
+    /// EV-DO Rev. B.
+    #[fail(display = "EV-DO Rev. B")]
+    EvdoRevB = 12,
+    /// CDMA 1xRTT.
+    #[fail(display = "1xRTT")]
+    OneXRtt = 13,
+    /// UMB.
+    #[fail(display = "UMB")]
+    Umb = 14,
+    /// CDMA 1xEV-DV.
+    #[fail(display = "1xEV-DV")]
+    OneXEvdv = 15,
+    /// CDMA 3xRTT.
+    #[fail(display = "3xRTT")]
+    ThreeXRtt = 16,
+    /// HSPA+ with 64QAM.
+    #[fail(display = "HSPA+ (64QAM)")]
+    HspaPlus64Qam = 17,
+    /// HSPA+ with MIMO.
+    #[fail(display = "HSPA+ (MIMO)")]
+    HspaPlusMimo = 18
+}
+/// System information, as returned from Huawei modems (`AT^SYSINFO`).
+///
+/// The system mode and submode are also given as raw values, in case the modem reports one this
+/// library doesn't know about.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    /// The network service status.
+    pub service_status: ServiceStatus,
+    /// Which domains the modem has service in.
+    pub service_domain: ServiceDomain,
+    /// Whether the modem is roaming.
+    pub roaming: bool,
+    /// The system mode (`None` if it isn't one we know about).
+    pub system_mode: Option<SystemMode>,
+    /// The system mode, as a raw value.
+    pub system_mode_raw: u32,
+    /// The state of the SIM card.
+    pub sim_state: SimState,
+    /// Whether the SIM card is locked (not reported by all modems).
+    pub locked: Option<bool>,
+    /// The system submode (`None` if it isn't reported, or isn't one we know about).
+    pub submode: Option<SystemSubmode>,
+    /// The system submode, as a raw value (not reported by all modems).
+    pub submode_raw: Option<u32>
+}
+impl FromAtValue for SystemInfo {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        let f = fields(value);
+        let system_mode_raw: u32 = field(f, 3, "SystemInfo.system_mode")?;
+        let submode_raw: Option<u32> = field(f, 6, "SystemInfo.submode")?;
+        Ok(SystemInfo {
+            service_status: field(f, 0, "SystemInfo.service_status")?,
+            service_domain: field(f, 1, "SystemInfo.service_domain")?,
+            roaming: field(f, 2, "SystemInfo.roaming")?,
+            system_mode: SystemMode::from_u32(system_mode_raw),
+            system_mode_raw,
+            sim_state: field(f, 4, "SystemInfo.sim_state")?,
+            locked: field(f, 5, "SystemInfo.locked")?,
+            submode: submode_raw.and_then(SystemSubmode::from_u32),
+            submode_raw
+        })
+    }
+}
+impl fmt::Display for SystemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}), ", self.service_status, self.service_domain)?;
+        if self.roaming {
+            write!(f, "roaming, ")?;
+        }
+        match self.system_mode {
+            Some(sm) => write!(f, "{}", sm)?,
+            None => write!(f, "unknown system mode {}", self.system_mode_raw)?
+        }
+        match (self.submode, self.submode_raw) {
+            (Some(sm), _) => write!(f, " ({})", sm)?,
+            (None, Some(raw)) => write!(f, " (unknown submode {})", raw)?,
+            (None, None) => {}
+        }
+        write!(f, ", {}", self.sim_state)?;
+        if self.locked == Some(true) {
+            write!(f, " (locked)")?;
+        }
+        Ok(())
+    }
+}
+/// Get system information from a Huawei modem (`AT^SYSINFO`).
+///
+/// This gets you the service status, SIM state and system mode all at once, so it's handy for
+/// figuring out why your modem isn't working.
+pub fn get_system_info(modem: &HuaweiModem) -> CommandFuture<SystemInfo> {
+    modem.execute(GetSystemInfo)
+}
+/// Get system information from a Huawei modem (`AT^SYSINFO`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetSystemInfo;
+impl AtCommandSpec for GetSystemInfo {
+    type Output = SystemInfo;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Execute { command: "^SYSINFO".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<SystemInfo> {
+        pkt.decode_named_response("^SYSINFO")
+    }
+}
+/// The system mode of the modem (from `AT^SYSINFOEX`).
+///
+/// Unlike `SystemMode`, this can tell you whether the modem is using LTE.
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExtendedSystemMode {
+    /// No service.
+    #[fail(display = "No service")]
+    NoService = 0,
+    /// GSM (2G).
+    #[fail(display = "GSM")]
+    Gsm = 1,
+    /// CDMA.
+    #[fail(display = "CDMA")]
+    Cdma = 2,
+    /// WCDMA (3G).
+    #[fail(display = "WCDMA")]
+    Wcdma = 3,
+    /// TD-SCDMA (3G).
+    #[fail(display = "TD-SCDMA")]
+    TdScdma = 4,
+    /// WiMAX.
+    #[fail(display = "WiMAX")]
+    Wimax = 5,
+    /// LTE (4G).
+    #[fail(display = "LTE")]
+    Lte = 6
+}
+/// Extended system information, as returned from newer Huawei modems (`AT^SYSINFOEX`).
+///
+/// As with `SystemInfo`, the system mode is also given as a raw value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedSystemInfo {
+    /// The network service status.
+    pub service_status: ServiceStatus,
+    /// Which domains the modem has service in.
+    pub service_domain: ServiceDomain,
+    /// Whether the modem is roaming.
+    pub roaming: bool,
+    /// The state of the SIM card.
+    pub sim_state: SimState,
+    /// Whether the SIM card is locked (not reported by all modems).
+    pub locked: Option<bool>,
+    /// The system mode (`None` if it isn't one we know about).
+    pub system_mode: Option<ExtendedSystemMode>,
+    /// The system mode, as a raw value.
+    pub system_mode_raw: u32,
+    /// The modem's name for the system mode (e.g. "LTE").
+    pub system_mode_name: Option<String>,
+    /// The system submode, as a raw value (consult your modem manual for what these mean).
+    pub submode: Option<u32>,
+    /// The modem's name for the system submode (e.g. "DC-HSPA+").
+    pub submode_name: Option<String>
+}
+impl FromAtValue for ExtendedSystemInfo {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        let f = fields(value);
+        let system_mode_raw: u32 = field(f, 5, "ExtendedSystemInfo.system_mode")?;
+        Ok(ExtendedSystemInfo {
+            service_status: field(f, 0, "ExtendedSystemInfo.service_status")?,
+            service_domain: field(f, 1, "ExtendedSystemInfo.service_domain")?,
+            roaming: field(f, 2, "ExtendedSystemInfo.roaming")?,
+            sim_state: field(f, 3, "ExtendedSystemInfo.sim_state")?,
+            locked: field(f, 4, "ExtendedSystemInfo.locked")?,
+            system_mode: ExtendedSystemMode::from_u32(system_mode_raw),
+            system_mode_raw,
+            system_mode_name: field(f, 6, "ExtendedSystemInfo.system_mode_name")?,
+            submode: field(f, 7, "ExtendedSystemInfo.submode")?,
+            submode_name: field(f, 8, "ExtendedSystemInfo.submode_name")?
         })
+    }
+}
+impl fmt::Display for ExtendedSystemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}), ", self.service_status, self.service_domain)?;
+        if self.roaming {
+            write!(f, "roaming, ")?;
+        }
+        match (self.system_mode, &self.system_mode_name) {
+            (Some(sm), _) => write!(f, "{}", sm)?,
+            (None, Some(name)) => write!(f, "{}", name)?,
+            (None, None) => write!(f, "unknown system mode {}", self.system_mode_raw)?
+        }
+        if let Some(ref sm) = self.submode_name {
+            write!(f, " ({})", sm)?;
+        }
+        write!(f, ", {}", self.sim_state)?;
+        if self.locked == Some(true) {
+            write!(f, " (locked)")?;
+        }
+        Ok(())
+    }
+}
+/// Get extended system information from a Huawei modem (`AT^SYSINFOEX`).
+///
+/// Older modems only support `get_system_info`, which can't report LTE.
+pub fn get_extended_system_info(modem: &HuaweiModem) -> CommandFuture<ExtendedSystemInfo> {
+    modem.execute(GetExtendedSystemInfo)
+}
+/// Get extended system information from a Huawei modem (`AT^SYSINFOEX`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GetExtendedSystemInfo;
+impl AtCommandSpec for GetExtendedSystemInfo {
+    type Output = ExtendedSystemInfo;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Execute { command: "^SYSINFOEX".into() }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<ExtendedSystemInfo> {
+        pkt.decode_named_response("^SYSINFOEX")
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::simulator::setup;
+
+    #[test]
+    fn extended_signal_quality() {
+        let (mut core, sim, modem) = setup();
+        sim.set_signal_quality(SignalQuality { rssi: 20, ber: 99 });
+        let sq = core.run(get_signal_quality(&modem)).unwrap();
+        assert_eq!(sq.rssi_dbm(), Some(-73));
+        sim.script("AT^HCSQ?", &["^HCSQ: \"LTE\",60,45,151,20", "OK"]);
+        let hcsq = core.run(get_huawei_signal_quality(&modem)).unwrap();
+        assert_eq!(hcsq.sysmode, SignalSystemMode::Lte);
+        assert_eq!(hcsq.rssi_dbm(), Some(-61));
+        assert_eq!(hcsq.rsrp_dbm(), Some(-96));
+        assert!((hcsq.sinr_db().unwrap() - 10.0).abs() < 0.01);
+        assert_eq!(hcsq.rsrq_db(), Some(-10.0));
+        assert_eq!(hcsq.rscp_dbm(), None);
+        sim.script("AT^HCSQ?", &["^HCSQ: \"WCDMA\",30,255,52", "OK"]);
+        let hcsq = core.run(get_huawei_signal_quality(&modem)).unwrap();
+        assert_eq!(hcsq.sysmode.to_string(), "WCDMA");
+        assert_eq!(hcsq.rscp, Some(255));
+        assert_eq!(hcsq.rscp_dbm(), None);
+        assert_eq!(hcsq.ecio_db(), Some(-6.5));
+        sim.script("AT+CESQ", &["+CESQ: 99,99,255,255,20,45", "OK"]);
+        let cesq = core.run(get_extended_signal_quality(&modem)).unwrap();
+        assert_eq!(cesq.rxlev_dbm(), None);
+        assert_eq!(cesq.rsrq_db(), Some(-10.0));
+        assert_eq!(cesq.rsrp_dbm(), Some(-96));
+        let hcsq = HuaweiSignalQuality {
+            sysmode: SignalSystemMode::Gsm,
+            rssi: Some(0), rscp: None, ecio: None, rsrp: None, sinr: None, rsrq: None
+        };
+        assert_eq!(hcsq.rssi_dbm(), Some(-121));
+        let bad = AtValue::Array(vec![AtValue::String("LTE".into()), AtValue::Integer(60), AtValue::String("x".into())]);
+        match HuaweiSignalQuality::from_at_value(&bad) {
+            Err(HuaweiError::InvalidField { ref field, .. }) if field == "HuaweiSignalQuality.rsrp" => {},
+            x => panic!("unexpected result: {:?}", x)
+        }
+    }
+    #[test]
+    fn operators() {
+        let (mut core, sim, modem) = setup();
+        sim.script("AT+COPS?", &["+COPS: 0,0,\"Vodafone UK\",2", "OK"]);
+        let op = core.run(get_operator(&modem)).unwrap();
+        assert_eq!(op, CurrentOperator {
+            mode: OperatorSelectionMode::Automatic,
+            format: Some(OperatorFormat::LongAlphanumeric),
+            operator: Some("Vodafone UK".into()),
+            act: Some(AccessTechnology::Utran)
+        });
+        sim.script("AT+COPS?", &["+COPS: 2", "OK"]);
+        assert_eq!(core.run(get_operator(&modem)).unwrap().operator, None);
+        sim.script("AT+COPS=?", &[
+            "+COPS: (2,\"Vodafone UK\",\"voda UK\",\"23415\",2),(3,\"O2 - UK\",\"O2 - UK\",\"23410\",0),,(0,1,2,3,4),(0,1,2)",
+            "OK"
+        ]);
+        let ops = core.run(scan_operators(&modem)).unwrap();
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[0].status, OperatorStatus::Current);
+        assert_eq!(ops[1].numeric, Some("23410".into()));
+        assert_eq!(ops[1].act, Some(AccessTechnology::Gsm));
+        for _ in 0..3 {
+            sim.script("AT+COPS=", &["OK"]);
+        }
+        core.run(set_operator_format(&modem, OperatorFormat::Numeric)).unwrap();
+        core.run(select_operator(&modem, OperatorSelection::Manual {
+            format: OperatorFormat::Numeric,
+            operator: "23410".into(),
+            act: Some(AccessTechnology::Utran),
+            fallback: true
+        })).unwrap();
+        core.run(select_operator(&modem, OperatorSelection::Automatic)).unwrap();
+        assert_eq!(&sim.received_commands()[4..], &[
+            "AT+COPS=3,2",
+            "AT+COPS=4,2,\"23410\",2",
+            "AT+COPS=0"
+        ]);
+    }
+    #[test]
+    fn system_info() {
+        let (mut core, sim, modem) = setup();
+        sim.script("AT^SYSINFO", &["^SYSINFO:2,3,0,5,1,,9", "OK"]);
+        let info = core.run(get_system_info(&modem)).unwrap();
+        assert_eq!(info, SystemInfo {
+            service_status: ServiceStatus::Valid,
+            service_domain: ServiceDomain::PsAndCs,
+            roaming: false,
+            system_mode: Some(SystemMode::Wcdma),
+            system_mode_raw: 5,
+            sim_state: SimState::Valid,
+            locked: None,
+            submode: Some(SystemSubmode::HspaPlus),
+            submode_raw: Some(9)
+        });
+        assert_eq!(info.to_string(), "Valid service (PS and CS service), WCDMA (HSPA+), Valid SIM");
+        sim.script("AT^SYSINFO", &["^SYSINFO:2,2,0,9,1,0,99", "OK"]);
+        let info = core.run(get_system_info(&modem)).unwrap();
+        assert_eq!((info.system_mode, info.system_mode_raw), (None, 9));
+        assert_eq!((info.submode, info.submode_raw), (None, Some(99)));
+        assert_eq!(info.to_string(), "Valid service (PS service only), unknown system mode 9 (unknown submode 99), Valid SIM");
+        sim.script("AT^SYSINFOEX", &["^SYSINFOEX:2,3,1,1,,6,\"LTE\",101,\"LTE\"", "OK"]);
+        let info = core.run(get_extended_system_info(&modem)).unwrap();
+        assert_eq!(info.system_mode, Some(ExtendedSystemMode::Lte));
+        assert!(info.roaming);
+        assert_eq!(info.submode, Some(101));
+        assert_eq!(info.to_string(), "Valid service (PS and CS service), roaming, LTE (LTE), Valid SIM");
+        sim.script("AT^SYSINFOEX", &["^SYSINFOEX:2,3,0,1,,7,\"NR\",111,\"NR\"", "OK"]);
+        let info = core.run(get_extended_system_info(&modem)).unwrap();
+        assert_eq!((info.system_mode, info.system_mode_raw), (None, 7));
+        assert_eq!(info.to_string(), "Valid service (PS and CS service), NR (NR), Valid SIM");
+    }
 }
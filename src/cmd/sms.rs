@@ -12,17 +12,17 @@
 //!
 //! **NB:** You *MUST* configure the modem for PDU mode by calling `send_sms_textmode` with `false`
 //! as argument before sending PDU-mode commands. Failure to do so will result in some fun times.
-use crate::{HuaweiModem};
+use crate::{HuaweiModem, CommandFuture};
 use crate::at::*;
+use crate::cmd::{AtCommandSpec, validate_number};
+use crate::convert::FromAtValue;
 use crate::errors::*;
-use futures::Future;
 use crate::pdu::{HexData, Pdu, AddressType, DeliverPdu};
 use std::convert::TryFrom;
-use crate::util::HuaweiFromPrimitive;
 
 /// The storage status of an SMS message (returned in `AT+CMGL`).
 #[repr(u8)]
-#[derive(Fail, Debug, FromPrimitive, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MessageStatus {
     /// Received and unread.
     #[fail(display = "Unread")]
@@ -118,133 +118,220 @@ pub struct SmsMessage {
 /// 
 /// Also note that this **may not necessarily be supported** by all modems! (in which case you'll have
 /// to fall back on polling).
-pub fn set_new_message_indications(modem: &mut HuaweiModem, mode: NewMessageNotification, mt: NewMessageStorage) -> impl Future<Item = (), Error = HuaweiError> {
-    modem.send_raw(AtCommand::Equals {
-        param: "+CNMI".into(),
-        value: AtValue::Array(vec![
-            AtValue::Integer(mode as u32),
-            AtValue::Integer(mt as u32)
-        ])
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
-        Ok(())
-    })
+pub fn set_new_message_indications(modem: &HuaweiModem, mode: NewMessageNotification, mt: NewMessageStorage) -> CommandFuture<()> {
+    modem.execute(SetNewMessageIndications { mode, mt })
+}
+/// Controls whether to send new message indications to the TE (`AT+CNMI`).
+///
+/// See `set_new_message_indications`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SetNewMessageIndications {
+    /// Whether to send indications.
+    pub mode: NewMessageNotification,
+    /// How to store new messages, and what indications to send.
+    pub mt: NewMessageStorage
+}
+impl AtCommandSpec for SetNewMessageIndications {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+CNMI".into(),
+            value: AtValue::Array(vec![
+                AtValue::Integer(self.mode as u32),
+                AtValue::Integer(self.mt as u32)
+            ])
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
 }
 /// Set the address of the SMS Service Center (`AT+CSCA`).
 ///
 /// You may need to configure this with the value provided by your network provider before being
 /// able to send SMSes.
-pub fn set_smsc_addr(modem: &mut HuaweiModem, sca: String, tosca: Option<AddressType>) -> impl Future<Item = (), Error = HuaweiError> {
-    let mut arr = vec![AtValue::String(sca)];
-    if let Some(t) = tosca {
-        let t: u8 = t.into();
-        arr.push(AtValue::Integer(t as u32));
-    }
-    modem.send_raw(AtCommand::Equals {
-        param: "+CSCA".into(),
-        value: AtValue::Array(arr)
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
-        Ok(())
-    })
+pub fn set_smsc_addr(modem: &HuaweiModem, sca: String, tosca: Option<AddressType>) -> CommandFuture<()> {
+    modem.execute(SetSmscAddr { sca, tosca })
+}
+/// Set the address of the SMS Service Center (`AT+CSCA`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetSmscAddr {
+    /// The address.
+    pub sca: String,
+    /// The type of the address, if it needs specifying.
+    pub tosca: Option<AddressType>
+}
+impl AtCommandSpec for SetSmscAddr {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        let mut arr = vec![AtValue::String(self.sca.clone())];
+        if let Some(t) = self.tosca {
+            let t: u8 = t.into();
+            arr.push(AtValue::Integer(t as u32));
+        }
+        AtCommand::Equals {
+            param: "+CSCA".into(),
+            value: AtValue::Array(arr)
+        }
+    }
+    fn validate(&self) -> HuaweiResult<()> {
+        validate_number(&self.sca)
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
+}
+/// Delete a message from the modem's message store (`AT+CMGD`).
+pub fn del_sms_pdu(modem: &HuaweiModem, del: DeletionOptions) -> CommandFuture<()> {
+    modem.execute(DelSms(del))
 }
 /// Delete a message from the modem's message store (`AT+CMGD`).
-pub fn del_sms_pdu(modem: &mut HuaweiModem, del: DeletionOptions) -> impl Future<Item = (), Error = HuaweiError> {
-    use self::DeletionOptions::*;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DelSms(pub DeletionOptions);
+impl AtCommandSpec for DelSms {
+    type Output = ();
 
-    let (index, delflag) = match del {
-        Indexed(i) => (i, 0),
-        DeleteRead => (0, 1),
-        DeleteReadAndSent => (0, 2),
-        DeleteReadAndOutgoing => (0, 3),
-        DeleteAll => (0, 4)
-    };
-    modem.send_raw(AtCommand::Equals {
-        param: "+CMGD".into(),
-        value: AtValue::Array(vec![AtValue::Integer(index), AtValue::Integer(delflag)])
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
-        Ok(())
-    })
+    fn command(&self) -> AtCommand {
+        use self::DeletionOptions::*;
+
+        let (index, delflag) = match self.0 {
+            Indexed(i) => (i, 0),
+            DeleteRead => (0, 1),
+            DeleteReadAndSent => (0, 2),
+            DeleteReadAndOutgoing => (0, 3),
+            DeleteAll => (0, 4)
+        };
+        AtCommand::Equals {
+            param: "+CMGD".into(),
+            value: AtValue::Array(vec![AtValue::Integer(index), AtValue::Integer(delflag)])
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
 }
 /// List SMSes from the modem's message store, in PDU mode (`AT+CMGL`).
 ///
 /// The modem must be configured properly for PDU mode first. See the module-level documentation for
 /// more information.
-pub fn list_sms_pdu(modem: &mut HuaweiModem, status: MessageStatus) -> impl Future<Item = Vec<SmsMessage>, Error = HuaweiError> {
-    modem.send_raw(AtCommand::Equals {
-        param: "+CMGL".into(),
-        value: AtValue::Integer(status as u32)
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
-        let mut cur = None;
+pub fn list_sms_pdu(modem: &HuaweiModem, status: MessageStatus) -> CommandFuture<Vec<SmsMessage>> {
+    modem.execute(ListSmsPdu { status })
+}
+/// List SMSes from the modem's message store, in PDU mode (`AT+CMGL`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ListSmsPdu {
+    /// Which messages to list.
+    pub status: MessageStatus
+}
+impl AtCommandSpec for ListSmsPdu {
+    type Output = Vec<SmsMessage>;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+CMGL".into(),
+            value: AtValue::Integer(self.status as u32)
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<Vec<SmsMessage>> {
         let mut ret = vec![];
-        for resp in pkt.responses {
-            match resp {
-                AtResponse::InformationResponse { param, response } => {
-                    assert_eq!(&param, "+CMGL");
-                    let list = response.get_array()?;
-                    let index = list.get(0)
-                        .ok_or(HuaweiError::TypeMismatch)?
-                        .get_integer()?;
-                    let stat = list.get(1)
-                        .ok_or(HuaweiError::TypeMismatch)?
-                        .get_integer()?;
-                    let stat = MessageStatus::from_integer(*stat)?;
-                    cur = Some((*index, stat));
-                },
-                AtResponse::Unknown(ref st) => {
-                    if st.trim().len() > 0 {
-                        let cur = cur.take().ok_or(HuaweiError::TypeMismatch)?;
-                        let hex = HexData::decode(st.trim())?;
-                        let pdu = DeliverPdu::try_from(&hex as &[u8])?;
-                        ret.push(SmsMessage {
-                            index: cur.0,
-                            status: cur.1,
-                            raw_pdu: hex.into(),
-                            pdu
-                        })
-                    }
-                },
-                _ => {}
-            }
+        for resp in pkt.information_responses("+CMGL")? {
+            let (index, stat): (u32, MessageStatus) = FromAtValue::from_at_value(resp.response)?;
+            let data = resp.data.first()
+                .ok_or(HuaweiError::TypeMismatch)?;
+            let hex = HexData::decode(data.trim())?;
+            let pdu = DeliverPdu::try_from(&hex as &[u8])?;
+            ret.push(SmsMessage {
+                index,
+                status: stat,
+                raw_pdu: hex.into(),
+                pdu
+            })
         }
         Ok(ret)
-    })
+    }
+}
+/// Set whether the modem will use text mode or not (`AT+CMGF`).
+pub fn set_sms_textmode(modem: &HuaweiModem, text: bool) -> CommandFuture<()> {
+    modem.execute(SetSmsTextmode { text })
 }
 /// Set whether the modem will use text mode or not (`AT+CMGF`).
-pub fn set_sms_textmode(modem: &mut HuaweiModem, text: bool) -> impl Future<Item = (), Error = HuaweiError> {
-    modem.send_raw(AtCommand::Equals {
-        param: "+CMGF".into(),
-        value: AtValue::Integer(if text { 1 } else { 0 })
-    }).and_then(|pkt| {
-        pkt.assert_ok()?;
-        Ok(())
-    })
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SetSmsTextmode {
+    /// `true` for text mode, `false` for PDU mode.
+    pub text: bool
+}
+impl AtCommandSpec for SetSmsTextmode {
+    type Output = ();
+
+    fn command(&self) -> AtCommand {
+        AtCommand::Equals {
+            param: "+CMGF".into(),
+            value: AtValue::Integer(if self.text { 1 } else { 0 })
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<()> {
+        pkt.assert_ok()
+    }
 }
 /// Send a message to a phone number, in text mode (`AT+CMGS`).
 ///
 /// Using text mode is recommended against for all but the most simple of cases; see the module-level
 /// documentation for more.
-pub fn send_sms_textmode(modem: &mut HuaweiModem, to: String, msg: String) -> impl Future<Item = u32, Error = HuaweiError> {
-    let text = format!("AT+CMGS=\"{}\"\n{}\x1A", to, msg);
-    modem.send_raw(AtCommand::Text { text, expected: vec!["+CMGS".into()] })
-        .and_then(|pkt| {
-           let rpl = pkt.extract_named_response("+CMGS")?
-               .get_integer()?;
-           Ok(*rpl)
-        })
+pub fn send_sms_textmode(modem: &HuaweiModem, to: String, msg: String) -> CommandFuture<u32> {
+    modem.execute(SendSmsTextmode { to, msg })
+}
+/// Send a message to a phone number, in text mode (`AT+CMGS`), returning its message reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendSmsTextmode {
+    /// The phone number to send the message to.
+    pub to: String,
+    /// The message text.
+    pub msg: String
+}
+impl AtCommandSpec for SendSmsTextmode {
+    type Output = u32;
+
+    fn command(&self) -> AtCommand {
+        AtCommand::WithPrompt {
+            command: Box::new(AtCommand::Equals {
+                param: "+CMGS".into(),
+                value: AtValue::String(self.to.clone())
+            }),
+            payload: self.msg.clone()
+        }
+    }
+    fn validate(&self) -> HuaweiResult<()> {
+        validate_number(&self.to)
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<u32> {
+        pkt.decode_named_response("+CMGS")
+    }
 }
 /// Send a message to a phone number, in PDU mode (`AT+CMGS`).
 ///
 /// See the `Pdu` documentation for information on how PDUs are made.
-pub fn send_sms_pdu(modem: &mut HuaweiModem, pdu: &Pdu) -> impl Future<Item = u32, Error = HuaweiError> {
-    let (data, len) = pdu.as_bytes();
-    let text = format!("AT+CMGS={}\n{}\x1A", len, HexData(&data));
-    modem.send_raw(AtCommand::Text { text, expected: vec!["+CMGS".into()] })
-        .and_then(|pkt| {
-           let rpl = pkt.extract_named_response("+CMGS")?
-               .get_integer()?;
-           Ok(*rpl)
-        })
+pub fn send_sms_pdu(modem: &HuaweiModem, pdu: &Pdu) -> CommandFuture<u32> {
+    modem.execute(SendSmsPdu(pdu.clone()))
+}
+/// Send a message in PDU mode (`AT+CMGS`), returning its message reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendSmsPdu(pub Pdu);
+impl AtCommandSpec for SendSmsPdu {
+    type Output = u32;
+
+    fn command(&self) -> AtCommand {
+        let (data, len) = self.0.as_bytes();
+        AtCommand::WithPrompt {
+            command: Box::new(AtCommand::Equals {
+                param: "+CMGS".into(),
+                value: AtValue::Integer(len as u32)
+            }),
+            payload: format!("{}", HexData(&data))
+        }
+    }
+    fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<u32> {
+        pkt.decode_named_response("+CMGS")
+    }
 }
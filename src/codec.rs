@@ -1,13 +1,143 @@
 //! Contains the Tokio codec used to decode the AT protocol.
 use tokio_codec::{Encoder, Decoder};
 use bytes::BytesMut;
-use crate::at::{AtCommand, AtResponse};
+use crate::at::{AtCommand, AtResponse, AtValue};
+use crate::charset::{Charset, latin1_bytes, latin1_decode};
 use failure;
 use crate::parse;
 
 /// Encodes AT commands into text to be sent to a modem, and decodes its responses into AT
 /// responses.
-pub struct AtCodec;
+///
+/// Lines that can't be parsed are skipped, and decoded as `AtResponse::Unknown`, so that one bit
+/// of line noise doesn't kill the connection to the modem.
+///
+/// Lines following information responses that are known to be followed by data (like the PDU
+/// after a `+CMGL` header) are decoded as `AtResponse::Data`, until a result code or another
+/// information response comes along.
+///
+/// Quoted strings in commands and in text-carrying responses (like `+COPS` or `+CPBR`) are
+/// encoded and decoded using the codec's character set (see the `charset` module), as are the
+/// payloads of text-mode `AT+CMGS` and `AT+CMGW` commands.
+#[derive(Debug, Clone, Default)]
+pub struct AtCodec {
+    charset: Charset,
+    /// Whether the next payload is text (as opposed to, say, a hex-encoded PDU).
+    text_payload: bool,
+    /// Whether the lines we're decoding are data lines following an information response.
+    in_data: bool
+}
+impl AtCodec {
+    /// Make a new codec, using the given character set.
+    pub fn new(charset: Charset) -> Self {
+        Self { charset, text_payload: false, in_data: false }
+    }
+    /// Get the character set in use.
+    pub fn charset(&self) -> Charset {
+        self.charset
+    }
+    /// Change the character set in use (which should match the modem's `AT+CSCS` setting).
+    pub fn set_charset(&mut self, charset: Charset) {
+        self.charset = charset;
+    }
+    fn decode_value(&self, val: &mut AtValue) {
+        match *val {
+            AtValue::String(ref mut st) => {
+                *st = self.charset.decode(&latin1_bytes(st));
+            },
+            AtValue::BracketedArray(ref mut vals) | AtValue::Array(ref mut vals) => {
+                for v in vals.iter_mut() {
+                    self.decode_value(v);
+                }
+            },
+            _ => {}
+        }
+    }
+    /// Work out whether a parsed line is actually a data line, keeping track of whether we're
+    /// in the middle of some data.
+    fn classify(&mut self, resp: AtResponse, line: &[u8]) -> AtResponse {
+        match resp {
+            AtResponse::InformationResponse { ref param, .. }
+                if param.starts_with('+') || param.starts_with('^') => {
+                self.in_data = DATA_RESPONSES.contains(&(param as &str));
+            },
+            AtResponse::ResultCode(_) | AtResponse::Prompt => self.in_data = false,
+            _ if self.in_data => return AtResponse::Data(latin1_decode(line)),
+            _ => {}
+        }
+        resp
+    }
+    fn encode_value(&self, val: &mut AtValue) {
+        match *val {
+            AtValue::String(ref mut st) => {
+                *st = latin1_decode(&self.charset.encode(st));
+            },
+            AtValue::BracketedArray(ref mut vals) | AtValue::Array(ref mut vals) => {
+                for v in vals.iter_mut() {
+                    self.encode_value(v);
+                }
+            },
+            _ => {}
+        }
+    }
+    /// Encode the strings in `cmd`, returning the bytes to send to the modem.
+    fn encode_command(&mut self, mut cmd: AtCommand) -> Vec<u8> {
+        if let AtCommand::WithPrompt { command, .. } = cmd {
+            cmd = *command;
+        }
+        if let AtCommand::Equals { ref param, ref mut value } = cmd {
+            let first = match *value {
+                AtValue::Array(ref vals) => vals.first(),
+                ref v => Some(v)
+            };
+            self.text_payload = (param == "+CMGS" || param == "+CMGW") &&
+                first.map(|v| v.is_string()).unwrap_or(false);
+            if !UNENCODED_PARAMS.contains(&(param as &str)) {
+                self.encode_value(value);
+            }
+        }
+        let line = format!("\r\n{}\r\n", cmd);
+        match self.charset {
+            // Don't let anything that isn't ASCII sneak through in, say, an `AtCommand::Text`.
+            Charset::Ira => Charset::Ira.encode(&line),
+            _ => latin1_bytes(&line)
+        }
+    }
+}
+
+/// Information responses that are followed by lines of data.
+const DATA_RESPONSES: &[&str] = &["+CMGL", "+CMGR", "+CMT", "+CDS", "+CBM"];
+
+/// Commands whose string parameters are always sent as-is, rather than in the TE character set
+/// (since they're names of character sets, storages, or statuses, or a PIN).
+const UNENCODED_PARAMS: &[&str] = &["+CSCS", "+CPMS", "+CPBS", "+CMGL", "+CPIN"];
+
+/// Information responses whose string parameters are in the TE character set (other responses'
+/// strings, like the hex LAC and CI in `+CREG`, are left alone).
+const ENCODED_RESPONSES: &[&str] = &[
+    "+COPS", "+CPBR", "+CPBF", "+CUSD", "+CNUM", "+CSCA", "+CLIP", "+COLP", "+CCWA",
+    "+CMGL", "+CMGR", "+CMT"
+];
+
+/// How much unparseable data without a line ending to put up with before discarding it.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Something to be sent to the modem by `AtCodec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtFrame {
+    /// An AT command, sent on its own line.
+    Command(AtCommand),
+    /// The payload of a command (see `AtCommand::WithPrompt`), sent in response to the `> `
+    /// prompt and terminated with Ctrl-Z.
+    Payload(String),
+    /// An ESC character, sent in response to the `> ` prompt to abort the command.
+    Cancel
+}
+impl From<AtCommand> for AtFrame {
+    fn from(cmd: AtCommand) -> AtFrame {
+        AtFrame::Command(cmd)
+    }
+}
 
 impl Decoder for AtCodec {
     type Item = Vec<AtResponse>;
@@ -16,42 +146,221 @@ impl Decoder for AtCodec {
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         use nom::IResult;
         trace!("decoding data: {:?}", src);
-        let (point, data) = match parse::responses(src) {
-            IResult::Done(rest, data) => {
-                if data.len() == 0 {
-                    return Ok(None);
+        loop {
+            let (point, data) = match parse::response_lines(src) {
+                IResult::Done(rest, data) => (rest.len(), data),
+                IResult::Error(e) => {
+                    // Skip to the next line ending, and hand the bad line up as an `Unknown`
+                    // response, instead of giving up on the modem entirely.
+                    let end = match src.iter().position(|&b| b == b'\n') {
+                        Some(p) => p + 1,
+                        None if src.len() > MAX_LINE_LENGTH => src.len(),
+                        None => return Ok(None)
+                    };
+                    let bad = src.split_to(end);
+                    let text = latin1_decode(&bad).trim().to_string();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    if self.in_data {
+                        return Ok(Some(vec![AtResponse::Data(latin1_decode(&bad).trim_end().into())]));
+                    }
+                    warn!("skipping malformed data from modem ({:?}): {:?}", e, bad);
+                    return Ok(Some(vec![AtResponse::Unknown(text)]));
+                },
+                IResult::Incomplete(_) => return Ok(None)
+            };
+            let data = data.into_iter()
+                .map(|(resp, line)| self.classify(resp, line))
+                .collect::<Vec<_>>();
+            let len = src.len().saturating_sub(point);
+            src.split_to(len);
+            if !data.is_empty() {
+                let mut data = data;
+                if self.charset != Charset::Ira && self.charset != Charset::Latin1 {
+                    for resp in data.iter_mut() {
+                        if let AtResponse::InformationResponse { ref param, ref mut response } = *resp {
+                            if ENCODED_RESPONSES.contains(&(param as &str)) {
+                                self.decode_value(response);
+                            }
+                        }
+                    }
                 }
-                (rest.len(), data)
-            },
-            IResult::Error(e) => return Err(e.into()),
-            IResult::Incomplete(_) => return Ok(None)
-        };
-        let len = src.len().saturating_sub(point);
-        src.split_to(len);
-        Ok(Some(data))
+                return Ok(Some(data));
+            }
+            if len == 0 {
+                return Ok(None);
+            }
+        }
     }
 }
 impl Encoder for AtCodec {
-    type Item = AtCommand;
+    type Item = AtFrame;
     type Error = failure::Error;
 
-    fn encode(&mut self, item: AtCommand, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        use std::fmt::Write;
-        use bytes::BufMut;
-
-        trace!("sending data: {}", item);
-        let data = format!("\r\n{}\r\n", item);
-        let data_len = data.as_bytes().len();
-        let rem = dst.remaining_mut();
-        let delta = data_len.saturating_sub(rem);
-        if data_len > rem {
-            dst.reserve(data_len * 2);
-        }
-        dst.write_str(&data)
-            .map_err(|e| {
-                error!("writing to AtCodec buffer failed: rem {} len {} delta {}", rem, data_len, delta);
-                e
-            })?;
+    fn encode(&mut self, item: AtFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        trace!("sending data: {:?}", item);
+        let data = match item {
+            AtFrame::Command(cmd) => self.encode_command(cmd),
+            AtFrame::Payload(p) => {
+                let mut data = if self.text_payload {
+                    self.charset.encode(&p)
+                }
+                else {
+                    p.into_bytes()
+                };
+                data.push(0x1A);
+                data
+            },
+            AtFrame::Cancel => vec![0x1B]
+        };
+        dst.extend_from_slice(&data);
         Ok(())
     }
 }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at::AtResultCode;
+
+    #[test]
+    fn resync_on_garbage() {
+        let mut src = BytesMut::from(&b"\r\nOK\r\n\r\n\xFFjunk\rmore\r\n \r\n+CSQ: 17,99\r\n"[..]);
+        let mut codec = AtCodec::default();
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(vec![AtResponse::ResultCode(AtResultCode::Ok)]));
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(vec![AtResponse::Unknown("\u{FF}junk\rmore".into())]));
+        match codec.decode(&mut src).unwrap() {
+            Some(ref r) if r.len() == 1 && r[0].is_information_response() => {},
+            x => panic!("unexpected result: {:?}", x)
+        }
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert!(src.is_empty());
+    }
+    #[test]
+    fn data_lines() {
+        let mut src = BytesMut::from(&b"\r\n+CMGL: 0,1,,5\r\n0011223344\r\n+CMGL: 1,\"REC READ\",\"+44\"\r\nNote: hi\r\nthere\r\n\r\nOK\r\n"[..]);
+        let mut codec = AtCodec::default();
+        let resps = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(resps[1], AtResponse::Data("0011223344".into()));
+        assert_eq!(resps[3], AtResponse::Data("Note: hi".into()));
+        assert_eq!(resps[4], AtResponse::Data("there".into()));
+        assert_eq!(resps[5], AtResponse::ResultCode(AtResultCode::Ok));
+        let pkt = crate::at::AtResponsePacket { responses: resps, status: AtResultCode::Ok };
+        let msgs = pkt.information_responses("+CMGL").unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].data, vec!["0011223344"]);
+        assert_eq!(msgs[1].data, vec!["Note: hi", "there"]);
+        let mut src = BytesMut::from(&b"\r\n+CSQ: 1,2\r\nstray\r\n"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap()[1], AtResponse::Unknown("stray".into()));
+    }
+    #[test]
+    fn urc_after_prompt() {
+        let mut src = BytesMut::from(&b"\r\n> \r\n+CMTI: \"SM\",3\r\n"[..]);
+        let mut codec = AtCodec::default();
+        let resps = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(resps, vec![
+            AtResponse::Prompt,
+            AtResponse::InformationResponse {
+                param: "+CMTI".into(),
+                response: AtValue::Array(vec![AtValue::String("SM".into()), AtValue::Integer(3)])
+            }
+        ]);
+        assert!(src.is_empty());
+    }
+    #[test]
+    fn charsets() {
+        let mut codec = AtCodec::new(Charset::Ucs2);
+        let mut dst = BytesMut::new();
+        let cmgs = AtCommand::Equals {
+            param: "+CMGS".into(),
+            value: AtValue::String("+4412".into())
+        };
+        codec.encode(AtFrame::Command(cmgs), &mut dst).unwrap();
+        codec.encode(AtFrame::Payload("Hé".into()), &mut dst).unwrap();
+        let cpms = AtCommand::Equals {
+            param: "+CPMS".into(),
+            value: AtValue::String("SM".into())
+        };
+        codec.encode(AtFrame::Command(cpms), &mut dst).unwrap();
+        assert_eq!(&dst[..], &b"\r\nAT+CMGS=\"002B0034003400310032\"\r\n004800E9\x1A\r\nAT+CPMS=\"SM\"\r\n"[..]);
+
+        let mut gsm = AtCodec::new(Charset::Gsm);
+        let mut gsm_dst = BytesMut::new();
+        let cusd = AtCommand::Equals {
+            param: "+CUSD".into(),
+            value: AtValue::String("@_\u{20AC}".into())
+        };
+        gsm.encode(AtFrame::Command(cusd), &mut gsm_dst).unwrap();
+        assert_eq!(&gsm_dst[..], &b"\r\nAT+CUSD=\"\\00\\11\\1Be\"\r\n"[..]);
+
+        let mut src = BytesMut::from(&b"\r\n+COPS: 0,0,\"004F0070\",2\r\n+CMTI: \"SM\",3\r\n"[..]);
+        let resps = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(resps[0], AtResponse::InformationResponse {
+            param: "+COPS".into(),
+            response: AtValue::Array(vec![
+                AtValue::Integer(0),
+                AtValue::Integer(0),
+                AtValue::String("Op".into()),
+                AtValue::Integer(2)
+            ])
+        });
+        assert_eq!(resps[1], AtResponse::InformationResponse {
+            param: "+CMTI".into(),
+            response: AtValue::Array(vec![AtValue::String("SM".into()), AtValue::Integer(3)])
+        });
+        let mut src = BytesMut::from(&b"\r\n+CREG: 2,1,\"1A2B\",\"00C3F1A5\"\r\n"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap()[0], AtResponse::InformationResponse {
+            param: "+CREG".into(),
+            response: AtValue::Array(vec![
+                AtValue::Integer(2),
+                AtValue::Integer(1),
+                AtValue::String("1A2B".into()),
+                AtValue::String("00C3F1A5".into())
+            ])
+        });
+        codec.set_charset(Charset::Ira);
+        let mut src = BytesMut::from(&b"\r\n+COPS: 0,0,\"Op\xE9ra\",2\r\n"[..]);
+        match codec.decode(&mut src).unwrap().unwrap()[0] {
+            AtResponse::InformationResponse { ref response, .. } => {
+                assert_eq!(response.get_array().unwrap()[2], AtValue::String("Opéra".into()));
+            },
+            ref x => panic!("unexpected response: {:?}", x)
+        }
+    }
+    #[test]
+    fn latin1_and_ira_round_trip() {
+        let cpbw = || AtCommand::Equals {
+            param: "+CPBW".into(),
+            value: AtValue::Array(vec![
+                AtValue::Integer(1),
+                AtValue::Empty,
+                AtValue::Empty,
+                AtValue::String("Op\u{E9}ra".into())
+            ])
+        };
+        let cmgs = || AtCommand::Equals {
+            param: "+CMGS".into(),
+            value: AtValue::String("+4412".into())
+        };
+        let mut codec = AtCodec::new(Charset::Latin1);
+        let mut dst = BytesMut::new();
+        codec.encode(AtFrame::Command(cpbw()), &mut dst).unwrap();
+        codec.encode(AtFrame::Command(cmgs()), &mut dst).unwrap();
+        codec.encode(AtFrame::Payload("Caf\u{E9}".into()), &mut dst).unwrap();
+        assert_eq!(&dst[..], &b"\r\nAT+CPBW=1,,,\"Op\xE9ra\"\r\n\r\nAT+CMGS=\"+4412\"\r\nCaf\xE9\x1A"[..]);
+        let mut src = BytesMut::from(&b"\r\n+CPBR: 1,\"\",129,\"Op\xE9ra\"\r\n"[..]);
+        match codec.decode(&mut src).unwrap().unwrap()[0] {
+            AtResponse::InformationResponse { ref response, .. } => {
+                assert_eq!(response.get_array().unwrap()[3], AtValue::String("Op\u{E9}ra".into()));
+            },
+            ref x => panic!("unexpected response: {:?}", x)
+        }
+
+        let mut codec = AtCodec::new(Charset::Ira);
+        let mut dst = BytesMut::new();
+        codec.encode(AtFrame::Command(cpbw()), &mut dst).unwrap();
+        codec.encode(AtFrame::Command(cmgs()), &mut dst).unwrap();
+        codec.encode(AtFrame::Payload("Caf\u{E9}".into()), &mut dst).unwrap();
+        assert_eq!(&dst[..], &b"\r\nAT+CPBW=1,,,\"Op?ra\"\r\n\r\nAT+CMGS=\"+4412\"\r\nCaf?\x1A"[..]);
+    }
+}
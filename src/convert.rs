@@ -0,0 +1,183 @@
+//! Decoding `AtValue`s into more useful types.
+//!
+//! The `FromAtValue` trait converts an `AtValue` (usually the value of an information response)
+//! into some Rust type. It's implemented for integers, strings, `bool`s, `Option`s, `Vec`s and
+//! tuples; `#[derive(FromAtValue)]` implements it for your own types:
+//!
+//! - structs are decoded from an `AtValue::Array`, taking one element per field, in the order
+//!   the fields are declared (a single value counts as an array of one element, and missing
+//!   elements count as `AtValue::Empty`, so trailing `Option` fields can be left out).
+//! - fieldless enums are decoded from an `AtValue::Integer`, using their `FromPrimitive`
+//!   implementation (so they need to derive that as well).
+//!
+//! ```rust
+//! use huawei_modem::at::AtValue;
+//! use huawei_modem::convert::FromAtValue;
+//!
+//! #[derive(FromAtValue, Debug, PartialEq)]
+//! struct MessageHeader {
+//!     index: u32,
+//!     status: u32,
+//!     alpha: Option<String>,
+//!     length: u32
+//! }
+//!
+//! let val = AtValue::Array(vec![
+//!     AtValue::Integer(3),
+//!     AtValue::Integer(1),
+//!     AtValue::Empty,
+//!     AtValue::Integer(24)
+//! ]);
+//! let hdr = MessageHeader::from_at_value(&val).unwrap();
+//! assert_eq!(hdr, MessageHeader { index: 3, status: 1, alpha: None, length: 24 });
+//! ```
+//!
+//! When a field fails to decode, the error is a `HuaweiError::InvalidField`, which says which
+//! field it was.
+use num::FromPrimitive;
+use crate::at::AtValue;
+use crate::errors::{HuaweiError, HuaweiResult};
+use crate::util::HuaweiFromPrimitive;
+
+pub use huawei_modem_derive::FromAtValue;
+
+/// Something that can be decoded from an `AtValue`.
+pub trait FromAtValue: Sized {
+    /// Decode an `AtValue` into this type.
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self>;
+}
+/// Get the elements of an array (treating anything else as a one-element array, and
+/// `AtValue::Empty` as an empty one).
+#[doc(hidden)]
+pub fn fields(value: &AtValue) -> &[AtValue] {
+    match *value {
+        AtValue::Array(ref vals) => vals,
+        AtValue::Empty => &[],
+        ref v => ::std::slice::from_ref(v)
+    }
+}
+/// Decode element `idx` of `fields`, reporting errors as being in `name`.
+#[doc(hidden)]
+pub fn field<T: FromAtValue>(fields: &[AtValue], idx: usize, name: &str) -> HuaweiResult<T> {
+    T::from_at_value(fields.get(idx).unwrap_or(&AtValue::Empty))
+        .map_err(|e| HuaweiError::InvalidField {
+            field: name.into(),
+            error: Box::new(e)
+        })
+}
+/// Decode an enum from an `AtValue::Integer`.
+#[doc(hidden)]
+pub fn from_integer<T: FromPrimitive>(value: &AtValue) -> HuaweiResult<T> {
+    T::from_integer(*value.get_integer()?)
+}
+macro_rules! from_at_value_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromAtValue for $ty {
+                fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+                    let i = *value.get_integer()?;
+                    if i as u64 > <$ty>::MAX as u64 {
+                        return Err(HuaweiError::ValueOutOfRange(value.clone()));
+                    }
+                    Ok(i as $ty)
+                }
+            }
+        )*
+    }
+}
+from_at_value_integer!(u8, u16, u32, u64, usize, i32, i64);
+impl FromAtValue for bool {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        match *value.get_integer()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(HuaweiError::ValueOutOfRange(value.clone()))
+        }
+    }
+}
+/// Accepts both quoted and unquoted strings.
+impl FromAtValue for String {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        match *value {
+            AtValue::String(ref s) | AtValue::Unknown(ref s) => Ok(s.clone()),
+            _ => Err(HuaweiError::TypeMismatch)
+        }
+    }
+}
+impl FromAtValue for AtValue {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        Ok(value.clone())
+    }
+}
+/// `AtValue::Empty` decodes to `None`.
+impl<T> FromAtValue for Option<T> where T: FromAtValue {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        match *value {
+            AtValue::Empty => Ok(None),
+            ref v => Ok(Some(T::from_at_value(v)?))
+        }
+    }
+}
+/// Decodes each element of an array (or a bracketed array, like `(1,2,3)`).
+impl<T> FromAtValue for Vec<T> where T: FromAtValue {
+    fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+        let vals = match *value {
+            AtValue::BracketedArray(ref vals) => vals,
+            ref v => fields(v)
+        };
+        vals.iter().map(T::from_at_value).collect()
+    }
+}
+macro_rules! from_at_value_tuple {
+    ($($name:ident $idx:tt),*) => {
+        /// Decodes the elements of an array positionally.
+        impl<$($name),*> FromAtValue for ($($name,)*) where $($name: FromAtValue),* {
+            fn from_at_value(value: &AtValue) -> HuaweiResult<Self> {
+                let fields = fields(value);
+                Ok(($(field(fields, $idx, concat!("element ", stringify!($idx)))?,)*))
+            }
+        }
+    }
+}
+from_at_value_tuple!(A 0);
+from_at_value_tuple!(A 0, B 1);
+from_at_value_tuple!(A 0, B 1, C 2);
+from_at_value_tuple!(A 0, B 1, C 2, D 3);
+from_at_value_tuple!(A 0, B 1, C 2, D 3, E 4);
+from_at_value_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cmd::network::{RegistrationState, SignalQuality};
+
+    #[derive(FromAtValue, Debug, PartialEq)]
+    struct Entry(u8, Option<String>, Vec<u32>);
+    #[derive(FromAtValue, Debug, PartialEq)]
+    struct Nothing;
+
+    #[test]
+    fn decoding() {
+        let val = AtValue::Array(vec![AtValue::Integer(0), AtValue::Integer(5)]);
+        assert_eq!(<(u32, RegistrationState)>::from_at_value(&val).unwrap(), (0, RegistrationState::RegisteredRoaming));
+        assert_eq!(SignalQuality::from_at_value(&val).unwrap(), SignalQuality { rssi: 0, ber: 5 });
+        let val = AtValue::Array(vec![
+            AtValue::Integer(7),
+            AtValue::String("x".into()),
+            AtValue::BracketedArray(vec![AtValue::Integer(1), AtValue::Integer(2)])
+        ]);
+        assert_eq!(Entry::from_at_value(&val).unwrap(), Entry(7, Some("x".into()), vec![1, 2]));
+        assert_eq!(Entry::from_at_value(&AtValue::Integer(1)).unwrap(), Entry(1, None, vec![]));
+        assert_eq!(Nothing::from_at_value(&AtValue::Empty).unwrap(), Nothing);
+        match Entry::from_at_value(&AtValue::Integer(300)) {
+            Err(HuaweiError::InvalidField { ref field, ref error }) if field == "Entry.0" => {
+                assert!(matches!(**error, HuaweiError::ValueOutOfRange(_)));
+            },
+            x => panic!("unexpected result: {:?}", x)
+        }
+        let val = AtValue::Array(vec![AtValue::Integer(1), AtValue::String("x".into())]);
+        match SignalQuality::from_at_value(&val) {
+            Err(HuaweiError::InvalidField { ref field, .. }) if field == "SignalQuality.ber" => {},
+            x => panic!("unexpected result: {:?}", x)
+        }
+    }
+}
@@ -29,6 +29,56 @@ pub enum HuaweiError {
     /// any library feature somewhat...difficult.
     #[fail(display = "Failed to communicate with the background future (it's likely dead).")]
     FutureDied,
+    /// The modem didn't complete a command within its timeout.
+    ///
+    /// After a timeout, the library sends `AT+CSCS?`, and throws away everything the modem says
+    /// until that command's response turns up, so that a late reply to the timed-out command
+    /// isn't mistaken for the reply to the next one. Any URCs the modem sends in the meantime are
+    /// lost.
+    #[fail(display = "Timed out waiting for the modem to respond.")]
+    TimedOut,
+    /// The modem went away while a command was in progress (see the `supervisor` module).
+    #[fail(display = "The modem disconnected while processing the command.")]
+    Disconnected,
+    /// The request queue was full (see `queue::QueueFullPolicy::Error`).
+    #[fail(display = "The request queue is full.")]
+    QueueFull,
+    /// A transcript couldn't be parsed (see the `transcript` module).
+    #[fail(display = "Invalid transcript on line {}: {}", line, reason)]
+    InvalidTranscript {
+        /// The line number (starting from 1).
+        line: usize,
+        /// What was wrong with it.
+        reason: String
+    },
+    /// A URC subscriber fell behind, and this many URCs were dropped (see
+    /// `urc::OverflowPolicy::LagError`).
+    #[fail(display = "URC subscriber fell behind; {} URCs were dropped.", _0)]
+    UrcLagged(u64),
+    /// A value can't be sent to the modem safely (see `AtCommand::validate`).
+    #[fail(display = "Can't safely send {:?} to the modem: {}", value, reason)]
+    UnsafeValue {
+        /// The offending value.
+        value: String,
+        /// What's wrong with it.
+        reason: &'static str
+    },
+    /// A field of a response couldn't be decoded (see the `convert` module).
+    #[fail(display = "Invalid value for {}: {}", field, error)]
+    InvalidField {
+        /// The field that failed to decode (e.g. `SignalQuality.rssi`).
+        field: String,
+        /// What went wrong.
+        error: Box<HuaweiError>
+    },
+    /// A step of the modem's initialisation sequence failed.
+    #[fail(display = "Initialisation command {} failed: {}", command, error)]
+    InitFailed {
+        /// The command that failed.
+        command: at::AtCommand,
+        /// What went wrong.
+        error: Box<HuaweiError>
+    },
     /// An error from the modem itself.
     #[fail(display = "Error from modem: {}", _0)]
     AtError(#[cause] at::AtResultCode),
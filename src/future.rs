@@ -1,11 +1,19 @@
-use std::fs::File;
-use tokio_file_unix::File as FileNb;
-use tokio_core::reactor::PollEvented;
-use crate::codec::AtCodec;
-use crate::at::{AtResponse, AtResponsePacket, AtCommand};
+use tokio_io::{AsyncRead, AsyncWrite};
+use crate::codec::{AtCodec, AtFrame};
+use crate::at::{AtResponse, AtResponsePacket, AtCommand, AtValue};
+use crate::charset::Charset;
+use std::sync::{Arc, Mutex};
 use futures::{Future, Sink, Stream, Async, Poll};
 use futures::sync::{oneshot, mpsc};
-use tokio_codec::Framed;
+use tokio_codec::{Decoder, Framed};
+use futures_timer::Delay;
+use std::cmp;
+use std::time::Duration;
+use crate::errors::{HuaweiError, HuaweiResult};
+use crate::supervisor::{ReconnectConfig, ReconnectEvent};
+use crate::urc::UrcPublisher;
+use crate::queue::{Priority, QueueSlot};
+use crate::InitStepResult;
 use failure;
 
 pub(crate) type ModemResponse = AtResponsePacket;
@@ -13,129 +21,506 @@ pub(crate) type ModemResponse = AtResponsePacket;
 pub(crate) struct ModemRequest {
     pub(crate) command: AtCommand,
     pub(crate) expected: Vec<String>,
-    pub(crate) notif: oneshot::Sender<ModemResponse>
+    pub(crate) timeout: Duration,
+    pub(crate) priority: Priority,
+    /// `None` if nobody's interested in the result (i.e. for initialisation commands).
+    pub(crate) notif: Option<oneshot::Sender<HuaweiResult<ModemResponse>>>,
+    pub(crate) slot: Option<QueueSlot>,
+    /// Whether this is a step of the initialisation sequence.
+    pub(crate) init: bool
+}
+impl ModemRequest {
+    /// Returns `true` if the `ModemResponseFuture` for this request has been dropped (and
+    /// arranges for the current task to be woken up if it is in future).
+    fn poll_cancelled(&mut self) -> bool {
+        match self.notif {
+            Some(ref mut n) => n.poll_cancel().unwrap().is_ready(),
+            None => false
+        }
+    }
 }
 struct ModemRequestState {
-    notif: oneshot::Sender<ModemResponse>,
+    notif: Option<oneshot::Sender<HuaweiResult<ModemResponse>>>,
     expected: Vec<String>,
-    responses: Vec<AtResponse>
+    responses: Vec<AtResponse>,
+    deadline: Delay,
+    payload: Option<String>,
+    /// The character set this command changes the modem to, if it's an `AT+CSCS=...`.
+    charset: Option<Charset>,
+    /// Whether this is the `AT+CSCS?` sent to resynchronise with the modem after a timeout.
+    resync: bool,
+    /// The command, if this is a step of the initialisation sequence.
+    init: Option<AtCommand>
+}
+impl ModemRequestState {
+    fn is_cancelled(&self) -> bool {
+        self.notif.as_ref().map(|n| n.is_canceled()).unwrap_or(false)
+    }
+    fn reply(self, res: HuaweiResult<ModemResponse>) {
+        if let Some(n) = self.notif {
+            let _ = n.send(res);
+        }
+    }
 }
-pub(crate) struct HuaweiModemFuture {
-    inner: Framed<PollEvented<FileNb<File>>, AtCodec>,
+pub(crate) struct Supervisor<R> {
+    open: R,
+    config: ReconnectConfig,
+    events: mpsc::UnboundedSender<ReconnectEvent>,
+    attempt: u32,
+    backoff: Duration,
+    delay: Delay,
+    /// Whether the modem has been opened and initialised successfully before.
+    opened: bool
+}
+/// The initialisation sequence used if the user doesn't provide one: just turn off echo.
+pub(crate) fn default_init() -> Vec<AtCommand> {
+    vec![AtCommand::Basic { command: "E".into(), number: Some(0) }]
+}
+pub(crate) struct HuaweiModemFuture<S, R = fn() -> HuaweiResult<S>> {
+    inner: Option<Framed<S, AtCodec>>,
+    supervisor: Option<Supervisor<R>>,
+    init: Vec<AtCommand>,
     rx: mpsc::UnboundedReceiver<ModemRequest>,
-    urc: mpsc::UnboundedSender<AtResponse>,
+    hub: UrcPublisher,
+    charset: Arc<Mutex<Charset>>,
     cur: Option<ModemRequestState>,
     requests: Vec<ModemRequest>,
+    init_results: Vec<InitStepResult>,
     fresh: bool,
 }
-impl HuaweiModemFuture {
+impl<S> HuaweiModemFuture<S> where S: AsyncRead + AsyncWrite {
     pub(crate) fn new(
-        inner: Framed<PollEvented<FileNb<File>>, AtCodec>,
+        inner: Framed<S, AtCodec>,
+        init: Vec<AtCommand>,
         rx: mpsc::UnboundedReceiver<ModemRequest>,
-        urc: mpsc::UnboundedSender<AtResponse>
+        hub: UrcPublisher,
+        charset: Arc<Mutex<Charset>>
     ) -> Self {
         Self {
-            inner, rx, urc,
+            inner: Some(inner),
+            supervisor: None,
+            init, rx, hub, charset,
             cur: None,
             requests: vec![],
+            init_results: vec![],
             fresh: true
         }
     }
 }
-impl Future for HuaweiModemFuture {
-    type Item = ();
-    type Error = failure::Error;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        trace!("HuaweiModemFuture woke up");
-        if self.fresh {
-            self.fresh = false;
-            debug!("first poll of future, imposing initial settings");
-            let (tx, _) = oneshot::channel();
-            let echo = AtCommand::Basic { command: "E".into(), number: Some(0) };
-            self.requests.insert(0, ModemRequest {
-                command: echo,
-                expected: vec![],
-                notif: tx
+impl<S, R> HuaweiModemFuture<S, R> where S: AsyncRead + AsyncWrite, R: FnMut() -> HuaweiResult<S> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_supervised(
+        inner: Framed<S, AtCodec>,
+        open: R,
+        config: ReconnectConfig,
+        events: mpsc::UnboundedSender<ReconnectEvent>,
+        rx: mpsc::UnboundedReceiver<ModemRequest>,
+        hub: UrcPublisher,
+        charset: Arc<Mutex<Charset>>
+    ) -> Self {
+        let backoff = config.initial_backoff;
+        let mut init = default_init();
+        init.extend(config.init_commands.iter().cloned());
+        Self {
+            inner: Some(inner),
+            init,
+            supervisor: Some(Supervisor {
+                open, config, events, backoff,
+                attempt: 1,
+                delay: Delay::new(backoff),
+                opened: false
+            }),
+            rx, hub, charset,
+            cur: None,
+            requests: vec![],
+            init_results: vec![],
+            fresh: true
+        }
+    }
+    fn framed(&mut self) -> &mut Framed<S, AtCodec> {
+        self.inner.as_mut().expect("HuaweiModemFuture used while disconnected")
+    }
+    fn queue_init(&mut self) {
+        debug!("imposing initial settings");
+        self.init_results.clear();
+        for (i, cmd) in self.init.iter().cloned().enumerate() {
+            self.requests.insert(i, ModemRequest {
+                timeout: cmd.default_timeout(),
+                expected: cmd.expected(),
+                command: cmd,
+                priority: Priority::High,
+                notif: None,
+                slot: None,
+                init: true
             });
         }
-        loop {
-            match self.inner.poll()? {
-                Async::Ready(r) => {
-                    let r = match r {
-                        Some(x) => x,
-                        None => {
-                            debug!("stream ran out, future exiting");
-                            return Ok(Async::Ready(()))
-                        }
-                    };
-                    if self.cur.is_some() {
-                        if r.iter().any(|x| x.is_result_code()) {
-                            let mut state = self.cur.take().unwrap();
-                            state.responses.extend(r);
-                            debug!("request completed with responses: {:?}", state.responses);
-                            let mut resps = vec![];
-                            let mut status = None;
-                            for resp in state.responses {
-                                match resp {
-                                    AtResponse::InformationResponse { param, response } => {
-                                        if state.expected.contains(&param) {
-                                            resps.push(AtResponse::InformationResponse { param, response });
-                                        }
-                                        else {
-                                            self.urc.unbounded_send(AtResponse::InformationResponse { param, response })?;
-                                        }
-                                    },
-                                    AtResponse::ResultCode(x) => {
-                                        status = Some(x);
-                                    },
-                                    x => resps.push(x)
+    }
+    /// Keep track of the modem's character set, so strings get encoded and decoded properly.
+    fn set_charset(&mut self, charset: Charset) {
+        debug!("modem character set is now {}", charset);
+        if let Some(ref mut inner) = self.inner {
+            inner.codec_mut().set_charset(charset);
+        }
+        *self.charset.lock().unwrap() = charset;
+    }
+    fn send_urc(&mut self, resp: AtResponse) {
+        self.hub.publish(&resp);
+    }
+    fn handle_prompt(&mut self) -> Result<(), failure::Error> {
+        let frame = match self.cur {
+            Some(ref mut state) => match state.payload.take() {
+                Some(p) if !state.is_cancelled() => {
+                    debug!("got prompt, sending payload");
+                    AtFrame::Payload(p)
+                },
+                _ => {
+                    debug!("got prompt, but request is cancelled or has no payload; sending ESC");
+                    AtFrame::Cancel
+                }
+            },
+            None => {
+                warn!("got prompt with no request outstanding; sending ESC");
+                AtFrame::Cancel
+            }
+        };
+        self.framed().start_send(frame)?;
+        Ok(())
+    }
+    /// Resynchronise with the modem after a timeout, so that a late reply to the timed-out command
+    /// doesn't get mistaken for the reply to the next one.
+    ///
+    /// This sends `AT+CSCS?` (which has a response we can recognise, unlike a bare `AT`), and
+    /// discards everything until its `+CSCS` response and result code turn up.
+    fn start_resync(&mut self) -> Result<(), failure::Error> {
+        debug!("resynchronising with modem");
+        let command = AtCommand::Read { param: "+CSCS".into() };
+        self.cur = Some(ModemRequestState {
+            notif: None,
+            expected: command.expected(),
+            responses: vec![],
+            deadline: Delay::new(command.default_timeout()),
+            payload: None,
+            charset: None,
+            resync: true,
+            init: None
+        });
+        self.framed().start_send(AtFrame::Command(command))?;
+        Ok(())
+    }
+    fn handle_responses(&mut self, mut r: Vec<AtResponse>) -> Result<(), failure::Error> {
+        if r.iter().any(|x| x.is_prompt()) {
+            r.retain(|x| !x.is_prompt());
+            self.handle_prompt()?;
+        }
+        if self.cur.is_some() {
+            if r.iter().any(|x| x.is_result_code()) {
+                let state = self.cur.as_mut().unwrap();
+                let is_cscs = |x: &AtResponse| match *x {
+                    AtResponse::InformationResponse { ref param, .. } => param == "+CSCS",
+                    _ => false
+                };
+                if state.resync && !state.responses.iter().chain(r.iter()).any(is_cscs) {
+                    debug!("discarding output while resynchronising: {:?} {:?}", state.responses, r);
+                    state.responses.clear();
+                    return Ok(());
+                }
+                let mut state = self.cur.take().unwrap();
+                state.responses.extend(r);
+                debug!("request completed with responses: {:?}", state.responses);
+                let mut resps = vec![];
+                let mut status = None;
+                // whether data lines belong to a URC, rather than a response to this command
+                let mut urc_data = false;
+                for resp in ::std::mem::take(&mut state.responses) {
+                    match resp {
+                        AtResponse::InformationResponse { param, response } => {
+                            if param == "+CSCS" {
+                                if let Some(cs) = response.get_string().ok().and_then(|s| Charset::from_name(s)) {
+                                    state.charset = Some(cs);
                                 }
-                            };
-                            let _ = state.notif.send(AtResponsePacket {
-                                responses: resps,
-                                status: status.unwrap()
-                            });
-                        }
-                        else {
-                            trace!("new responses: {:?}", r);
-                            self.cur.as_mut().unwrap().responses.extend(r);
-                        }
-                    }
-                    else {
-                        for resp in r {
-                            self.urc.unbounded_send(resp)?;
-                        }
+                            }
+                            urc_data = !state.expected.contains(&param);
+                            if urc_data {
+                                self.send_urc(AtResponse::InformationResponse { param, response });
+                            }
+                            else {
+                                resps.push(AtResponse::InformationResponse { param, response });
+                            }
+                        },
+                        AtResponse::Data(d) => {
+                            if urc_data {
+                                self.send_urc(AtResponse::Data(d));
+                            }
+                            else {
+                                resps.push(AtResponse::Data(d));
+                            }
+                        },
+                        AtResponse::ResultCode(x) => {
+                            status = Some(x);
+                        },
+                        x => resps.push(x)
                     }
-                },
-                Async::NotReady => {
-                    trace!("inner not ready");
-                    break;
+                };
+                let status = status.unwrap();
+                if let (true, Some(cs)) = (status.is_ok(), state.charset) {
+                    self.set_charset(cs);
                 }
+                let pkt = AtResponsePacket {
+                    responses: resps,
+                    status
+                };
+                match state.init.take() {
+                    Some(command) => self.init_step_done(command, Ok(pkt))?,
+                    None => state.reply(Ok(pkt))
+                }
+            }
+            else {
+                trace!("new responses: {:?}", r);
+                self.cur.as_mut().unwrap().responses.extend(r);
             }
         }
+        else {
+            for resp in r {
+                self.send_urc(resp);
+            }
+        }
+        Ok(())
+    }
+    /// Pick up new requests, and drop cancelled ones. Returns `true` if the `HuaweiModem` has
+    /// gone away.
+    fn poll_requests(&mut self) -> bool {
+        let mut gone = false;
         while let Async::Ready(r) = self.rx.poll().unwrap() {
             if let Some(r) = r {
-                debug!("got a new request: {:?}", r.command);
-                self.requests.push(r);
+                debug!("got a new request: {:?} (priority {:?})", r.command, r.priority);
+                // requests are kept in priority order, and FIFO within each priority
+                let pos = self.requests.iter()
+                    .position(|x| x.priority < r.priority)
+                    .unwrap_or(self.requests.len());
+                self.requests.insert(pos, r);
             }
             else {
-                debug!("receiver ran out, future exiting");
-                return Ok(Async::Ready(()));
+                gone = true;
+                break;
             }
         }
-        if self.cur.is_none() && self.requests.len() > 0 {
-            let req = self.requests.remove(0);
-            debug!("starting new request: {:?}", req.command);
-            self.inner.start_send(req.command)?;
-            self.cur = Some(ModemRequestState {
-                notif: req.notif,
-                expected: req.expected,
-                responses: vec![]
-            });
+        self.requests.retain_mut(|r| {
+            let cancelled = r.poll_cancelled();
+            if cancelled {
+                debug!("dropping cancelled request: {:?}", r.command);
+            }
+            !cancelled
+        });
+        gone
+    }
+    /// Talk to the modem. Returns `Ready` if the modem went away.
+    fn poll_connected(&mut self) -> Poll<(), failure::Error> {
+        loop {
+            match self.framed().poll()? {
+                Async::Ready(Some(r)) => self.handle_responses(r)?,
+                Async::Ready(None) => return Ok(Async::Ready(())),
+                Async::NotReady => {
+                    trace!("inner not ready");
+                    break;
+                }
+            }
+        }
+        loop {
+            if self.cur.is_none() && self.requests.len() > 0 {
+                let req = self.requests.remove(0);
+                debug!("starting new request: {:?}", req.command);
+                // free up the request's space in the queue
+                drop(req.slot);
+                let init = if req.init { Some(req.command.clone()) } else { None };
+                let (command, payload) = match req.command {
+                    AtCommand::WithPrompt { command, payload } => (*command, Some(payload)),
+                    c => (c, None)
+                };
+                let charset = match command {
+                    AtCommand::Equals { ref param, value: AtValue::String(ref cs) } if param == "+CSCS" => {
+                        Charset::from_name(cs)
+                    },
+                    _ => None
+                };
+                self.framed().start_send(AtFrame::Command(command))?;
+                self.cur = Some(ModemRequestState {
+                    notif: req.notif,
+                    expected: req.expected,
+                    responses: vec![],
+                    deadline: Delay::new(req.timeout),
+                    payload, charset,
+                    resync: false,
+                    init
+                });
+            }
+            let timed_out = match self.cur {
+                Some(ref mut state) => state.deadline.poll()?.is_ready(),
+                None => false
+            };
+            if !timed_out {
+                break;
+            }
+            let mut state = self.cur.take().unwrap();
+            if state.resync {
+                warn!("modem didn't respond while resynchronising; carrying on regardless");
+                continue;
+            }
+            warn!("request timed out; discarding responses: {:?}", state.responses);
+            if state.payload.is_some() {
+                debug!("request timed out waiting for prompt; sending ESC");
+                self.framed().start_send(AtFrame::Cancel)?;
+            }
+            match state.init.take() {
+                Some(command) => self.init_step_done(command, Err(HuaweiError::TimedOut))?,
+                None => state.reply(Err(HuaweiError::TimedOut))
+            }
+            self.start_resync()?;
         }
-        self.inner.poll_complete()?;
+        self.framed().poll_complete()?;
         Ok(Async::NotReady)
     }
+    fn disconnected(&mut self, reason: String) {
+        warn!("modem disconnected: {}", reason);
+        self.inner = None;
+        if let Some(state) = self.cur.take() {
+            state.reply(Err(HuaweiError::Disconnected));
+        }
+        // the whole init sequence gets queued again once the modem's reopened
+        self.requests.retain(|r| !r.init);
+        self.init_results.clear();
+        let sup = self.supervisor.as_mut().unwrap();
+        sup.attempt = 0;
+        sup.backoff = sup.config.initial_backoff;
+        sup.delay.reset(sup.backoff);
+        let _ = sup.events.unbounded_send(ReconnectEvent::Disconnected(reason));
+    }
+    /// Deal with the result of a step of the initialisation sequence.
+    ///
+    /// For supervised modems, a failed step fails with `HuaweiError::InitFailed` (which makes
+    /// `poll` treat the attempt to open the modem as failed); once every step has succeeded, the
+    /// results are sent off in a `ReconnectEvent`.
+    fn init_step_done(&mut self, command: AtCommand, res: HuaweiResult<AtResponsePacket>) -> Result<(), failure::Error> {
+        let sup = match self.supervisor {
+            Some(ref mut s) => s,
+            None => {
+                // without a supervisor, the init sequence is just `ATE0`, and nobody's listening
+                if let Err(e) = res.and_then(|pkt| pkt.assert_ok()) {
+                    warn!("initialisation command {} failed: {}", command, e);
+                }
+                return Ok(());
+            }
+        };
+        let response = match res.and_then(|pkt| pkt.assert_ok().map(|_| pkt)) {
+            Ok(r) => r,
+            Err(e) => return Err(HuaweiError::InitFailed { command, error: Box::new(e) }.into())
+        };
+        self.init_results.push(InitStepResult { command, response });
+        if self.requests.iter().any(|r| r.init) {
+            return Ok(());
+        }
+        let init = ::std::mem::take(&mut self.init_results);
+        let event = if sup.opened {
+            info!("modem reinitialised after {} attempt(s)", sup.attempt);
+            ReconnectEvent::Reconnected { attempts: sup.attempt, init }
+        }
+        else {
+            ReconnectEvent::Opened { init }
+        };
+        sup.opened = true;
+        let _ = sup.events.unbounded_send(event);
+        Ok(())
+    }
+    /// Record a failed attempt to open the modem, giving up if there have been too many.
+    fn attempt_failed(&mut self, error: String) -> Result<(), failure::Error> {
+        let sup = self.supervisor.as_mut().unwrap();
+        warn!("failed to open modem (attempt {}): {}", sup.attempt, error);
+        let _ = sup.events.unbounded_send(ReconnectEvent::AttemptFailed {
+            attempt: sup.attempt,
+            error
+        });
+        if sup.config.max_attempts.map(|m| sup.attempt >= m).unwrap_or(false) {
+            let _ = sup.events.unbounded_send(ReconnectEvent::GaveUp);
+            return Err(failure::format_err!("gave up reopening modem after {} attempts", sup.attempt));
+        }
+        sup.backoff = cmp::min(sup.backoff * 2, sup.config.max_backoff);
+        sup.delay.reset(sup.backoff);
+        Ok(())
+    }
+    /// Close the modem after its initialisation sequence failed, so it can be reopened.
+    fn init_failed(&mut self, error: HuaweiError) -> Result<(), failure::Error> {
+        self.inner = None;
+        if let Some(state) = self.cur.take() {
+            state.reply(Err(HuaweiError::Disconnected));
+        }
+        self.requests.retain(|r| !r.init);
+        self.init_results.clear();
+        self.attempt_failed(error.to_string())
+    }
+    /// Try to reopen the modem. Returns `Ready` once it's been reopened.
+    fn poll_reconnect(&mut self) -> Poll<(), failure::Error> {
+        loop {
+            let res = {
+                let sup = self.supervisor.as_mut().unwrap();
+                futures::try_ready!(sup.delay.poll());
+                sup.attempt += 1;
+                debug!("trying to reopen modem (attempt {})", sup.attempt);
+                (sup.open)()
+            };
+            match res {
+                Ok(stream) => {
+                    debug!("modem reopened; initialising it");
+                    self.inner = Some(AtCodec::default().framed(stream));
+                    self.set_charset(Charset::default());
+                    self.queue_init();
+                    return Ok(Async::Ready(()));
+                },
+                Err(e) => self.attempt_failed(e.to_string())?
+            }
+        }
+    }
 }
+impl<S, R> Future for HuaweiModemFuture<S, R> where S: AsyncRead + AsyncWrite, R: FnMut() -> HuaweiResult<S> {
+    type Item = ();
+    type Error = failure::Error;
 
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        trace!("HuaweiModemFuture woke up");
+        if self.fresh {
+            self.fresh = false;
+            self.queue_init();
+        }
+        loop {
+            if self.poll_requests() {
+                debug!("receiver ran out, future exiting");
+                return Ok(Async::Ready(()));
+            }
+            if self.inner.is_none() {
+                futures::try_ready!(self.poll_reconnect());
+            }
+            let reason = match self.poll_connected() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {
+                    if self.supervisor.is_none() {
+                        debug!("stream ran out, future exiting");
+                        return Ok(Async::Ready(()));
+                    }
+                    "end of stream".to_string()
+                },
+                Err(e) => {
+                    if self.supervisor.is_none() {
+                        return Err(e);
+                    }
+                    match e.downcast::<HuaweiError>() {
+                        Ok(e @ HuaweiError::InitFailed { .. }) => {
+                            self.init_failed(e)?;
+                            continue;
+                        },
+                        Ok(e) => e.to_string(),
+                        Err(e) => e.to_string()
+                    }
+                }
+            };
+            self.disconnected(reason);
+        }
+    }
+}
@@ -6,27 +6,59 @@
 //! look at [modem.rs](https://git.theta.eu.org/sms-irc.git/tree/src/modem.rs) inside that project
 //! to get a feel for how to use this library, as well as looking inside the `examples/`
 //! subdirectory to see some simple SMS sending/receiving examples.
+//!
+//! The library is built on futures 0.1 and `tokio-core`. If you'd rather use async/await on a
+//! modern runtime, enable the `async` feature: the futures returned by the `cmd` functions can
+//! then be `.await`ed directly, and `HuaweiModem::new_from_async_stream` lets you drive the modem
+//! from any executor.
 
 #[macro_use] extern crate log;
 #[macro_use] extern crate failure_derive;
 #[macro_use] extern crate nom;
 #[macro_use] extern crate derive_is_enum_variant;
 #[macro_use] extern crate num_derive;
+// so that `#[derive(FromAtValue)]` works inside this crate, too
+extern crate self as huawei_modem;
 
 use std::fs::{File, OpenOptions};
 use tokio_file_unix::File as FileNb;
 use crate::codec::AtCodec;
-use crate::at::{AtResponse, AtCommand};
-use futures::{Future, Poll};
+use crate::at::{AtResponse, AtResponsePacket, AtCommand};
+use futures::{Future, Async, Poll};
+use futures::future::{Either, Loop};
 use futures::sync::{oneshot, mpsc};
 use crate::future::{ModemRequest, ModemResponse, HuaweiModemFuture};
+use std::time::Duration;
 use tokio_core::reactor::Handle;
 use tokio_codec::Decoder;
+use tokio_io::{AsyncRead, AsyncWrite};
 pub use crate::errors::HuaweiResult;
 
 /// Bog-standard boxed future alias.
 pub type HuaweiFuture<T> = Box<dyn Future<Item = T, Error = errors::HuaweiError>>;
 
+/// Future returned by the command functions in the `cmd` module.
+///
+/// This is a futures 0.1 `Future`; if the `async` feature is enabled, it's also a
+/// `std::future::Future`, so you can `.await` it.
+#[must_use = "futures do nothing unless polled"]
+pub struct CommandFuture<T> {
+    inner: Box<dyn Future<Item = T, Error = errors::HuaweiError> + Send>
+}
+impl<T> CommandFuture<T> {
+    pub(crate) fn new<F>(fut: F) -> Self where F: Future<Item = T, Error = errors::HuaweiError> + Send + 'static {
+        Self { inner: Box::new(fut) }
+    }
+}
+impl<T> Future for CommandFuture<T> {
+    type Item = T;
+    type Error = errors::HuaweiError;
+
+    fn poll(&mut self) -> Poll<T, errors::HuaweiError> {
+        self.inner.poll()
+    }
+}
+
 macro_rules! check_offset {
     ($b:ident, $offset:ident, $reason:expr) => {
         if $b.get($offset).is_none() {
@@ -39,37 +71,138 @@ pub mod error_codes;
 pub mod errors;
 pub mod gsm_encoding;
 pub mod at;
+pub mod convert;
+pub mod charset;
 pub mod pdu;
 mod parse;
 pub mod codec;
 pub mod cmd;
+pub mod blocking;
+pub mod serial;
+pub mod supervisor;
+pub mod queue;
+pub mod simulator;
+pub mod transcript;
+pub mod urc;
+pub mod capabilities;
 mod util;
 mod future;
+#[cfg(feature = "async")]
+mod asynchronous;
 
 use std::path::Path;
+use std::os::unix::io::AsRawFd;
 use crate::errors::HuaweiError;
+use crate::serial::SerialConfig;
+use crate::supervisor::{ReconnectConfig, ReconnectEvent};
+use crate::urc::{UrcStream, UrcHub, UrcPublisher, UrcSubscription, SubscriptionConfig};
+use crate::capabilities::ModemCapabilities;
+use crate::charset::Charset;
+use crate::cmd::AtCommandSpec;
+use crate::queue::{Priority, QueueConfig, QueueFullPolicy, QueueLimit, QueueSlot};
+use std::sync::{Arc, Mutex};
 
 /// Future representing a response from the modem.
+///
+/// Like `CommandFuture`, this is also a `std::future::Future` if the `async` feature is enabled.
 pub struct ModemResponseFuture {
-    rx: Result<oneshot::Receiver<ModemResponse>, ()>
+    state: ResponseState
+}
+/// A request that hasn't made it into the queue yet.
+struct PendingRequest {
+    command: AtCommand,
+    expected: Vec<String>,
+    timeout: Duration,
+    priority: Priority
+}
+enum ResponseState {
+    /// Waiting for space in the request queue.
+    Queueing {
+        req: Option<PendingRequest>,
+        tx: mpsc::UnboundedSender<ModemRequest>,
+        limit: Arc<QueueLimit>
+    },
+    /// Queued, and waiting for the modem to respond.
+    Waiting(oneshot::Receiver<HuaweiResult<ModemResponse>>),
+    /// Failed before making it into the queue.
+    Failed(Option<HuaweiError>)
+}
+impl ResponseState {
+    fn enqueue(tx: &mpsc::UnboundedSender<ModemRequest>, req: PendingRequest, slot: QueueSlot) -> Self {
+        let (notif, rx) = oneshot::channel();
+        let PendingRequest { command, expected, timeout, priority } = req;
+        let req = ModemRequest {
+            command, expected, timeout, priority,
+            notif: Some(notif),
+            slot: Some(slot),
+            init: false
+        };
+        if tx.unbounded_send(req).is_err() {
+            ResponseState::Failed(Some(HuaweiError::FutureDied))
+        }
+        else {
+            ResponseState::Waiting(rx)
+        }
+    }
 }
 impl Future for ModemResponseFuture {
     type Item = ModemResponse;
     type Error = HuaweiError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        match self.rx {
-            Ok(ref mut rx) => Ok(rx.poll()?),
-            Err(_) => Err(HuaweiError::FutureDied)
+        loop {
+            let next = match self.state {
+                ResponseState::Queueing { ref mut req, ref tx, ref limit } => {
+                    let slot = match limit.poll_acquire() {
+                        Async::Ready(s) => s,
+                        Async::NotReady => return Ok(Async::NotReady)
+                    };
+                    let req = req.take()
+                        .expect("ModemResponseFuture polled after completion");
+                    ResponseState::enqueue(tx, req, slot)
+                },
+                ResponseState::Waiting(ref mut rx) => return match rx.poll()? {
+                    Async::Ready(res) => Ok(Async::Ready(res?)),
+                    Async::NotReady => Ok(Async::NotReady)
+                },
+                ResponseState::Failed(ref mut e) => return Err(e.take().unwrap_or(HuaweiError::FutureDied))
+            };
+            self.state = next;
         }
     }
 }
+/// The result of one step of a modem's initialisation sequence (see
+/// `HuaweiModem::new_from_stream_with_init`, or `supervisor::ReconnectEvent`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitStepResult {
+    /// The command that was sent.
+    pub command: AtCommand,
+    /// The modem's response to it.
+    pub response: AtResponsePacket
+}
 /// A connection to an AT/Huawei-style modem.
+///
+/// This is a cheap handle to the background future that actually talks to the modem: cloning it
+/// gives you another handle to the same modem, and commands sent through any of them go into the
+/// same queue (see the `queue` module). The connection is closed once all the handles are dropped.
+#[derive(Clone)]
 pub struct HuaweiModem {
     tx: mpsc::UnboundedSender<ModemRequest>,
-    urc: Option<mpsc::UnboundedReceiver<AtResponse>>
+    hub: Arc<Mutex<UrcHub>>,
+    reconnect: Arc<Mutex<Option<mpsc::UnboundedReceiver<ReconnectEvent>>>>,
+    limit: Arc<QueueLimit>,
+    charset: Arc<Mutex<Charset>>,
+    capabilities: Arc<Mutex<Option<ModemCapabilities>>>
 }
 impl HuaweiModem {
+    pub(crate) fn from_parts(tx: mpsc::UnboundedSender<ModemRequest>, hub: Arc<Mutex<UrcHub>>, charset: Arc<Mutex<Charset>>, reconnect: Option<mpsc::UnboundedReceiver<ReconnectEvent>>) -> Self {
+        Self {
+            tx, hub, charset,
+            reconnect: Arc::new(Mutex::new(reconnect)),
+            limit: QueueLimit::new(QueueConfig::default()),
+            capabilities: Arc::new(Mutex::new(None))
+        }
+    }
     /// Start talking to the modem at a specified file path.
     pub fn new_from_path<P: AsRef<Path>>(path: P, h: &Handle) -> HuaweiResult<Self> {
         let file = OpenOptions::new()
@@ -78,21 +211,128 @@ impl HuaweiModem {
             .open(path)?;
         Self::new_from_file(file, h)
     }
+    /// Start talking to the modem at a specified file path, applying the given serial line
+    /// settings to it first.
+    ///
+    /// Unlike `new_from_path`, this makes sure the TTY is in a sane state (e.g. raw mode), which
+    /// matters if some other program has messed with its settings. The path must refer to a TTY.
+    pub fn new_from_path_with_config<P: AsRef<Path>>(path: P, config: &SerialConfig, h: &Handle) -> HuaweiResult<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        config.apply(file.as_raw_fd())?;
+        Self::new_from_file(file, h)
+    }
     /// Start talking to the modem represented by a given file handle.
     ///
     /// The file handle provided must support non-blocking IO for this method to work.
     pub fn new_from_file(f: File, h: &Handle) -> HuaweiResult<Self> {
         let ev = FileNb::new_nb(f)?.into_io(h)?;
-        let framed = AtCodec.framed(ev);
+        Self::new_from_stream(ev, h)
+    }
+    /// Start talking to a modem over an arbitrary asynchronous byte stream.
+    ///
+    /// This is useful if your modem isn't a local TTY - for example, if it's exposed over a TCP
+    /// serial bridge, or if you want to talk to a simulated modem over an in-memory pipe.
+    pub fn new_from_stream<S>(stream: S, h: &Handle) -> HuaweiResult<Self> where S: AsyncRead + AsyncWrite + 'static {
+        Ok(Self::spawn(stream, future::default_init(), h))
+    }
+    fn spawn<S>(stream: S, init: Vec<AtCommand>, h: &Handle) -> Self where S: AsyncRead + AsyncWrite + 'static {
+        let framed = AtCodec::default().framed(stream);
         let (tx, rx) = mpsc::unbounded();
-        let (urctx, urcrx) = mpsc::unbounded();
-        let fut = HuaweiModemFuture::new(framed, rx, urctx);
+        let hub = Arc::new(Mutex::new(UrcHub::default()));
+        let charset = Arc::new(Mutex::new(Charset::default()));
+        let fut = HuaweiModemFuture::new(framed, init, rx, UrcPublisher::new(hub.clone()), charset.clone());
         h.spawn(fut.map_err(|e| {
             error!("HuaweiModemFuture failed: {}", e);
             error!("Backtrace: {}", e.backtrace());
             ()
         }));
-        Ok(Self { tx, urc: Some(urcrx) })
+        Self::from_parts(tx, hub, charset, None)
+    }
+    /// Start talking to a modem over an arbitrary asynchronous byte stream, running the given
+    /// initialisation sequence instead of the default one (which just sends `ATE0`).
+    ///
+    /// The commands in `init` are sent in order before anything else. The returned future
+    /// resolves to the modem and the results of each step once they've all succeeded; if any step
+    /// fails, the modem is closed again, and the future fails with `HuaweiError::InitFailed`.
+    ///
+    /// You probably want to include `ATE0` (disable echo) somewhere in your sequence (after
+    /// any `ATZ`!), since the library doesn't cope well with command echo.
+    pub fn new_from_stream_with_init<S>(stream: S, init: Vec<AtCommand>, h: &Handle) -> HuaweiFuture<(Self, Vec<InitStepResult>)> where S: AsyncRead + AsyncWrite + 'static {
+        let modem = Self::spawn(stream, vec![], h);
+        let fut = futures::future::loop_fn((modem, init.into_iter(), vec![]), |(modem, mut init, mut results)| {
+            let command = match init.next() {
+                Some(c) => c,
+                None => return Either::A(futures::future::ok(Loop::Break((modem, results))))
+            };
+            debug!("running init step: {}", command);
+            Either::B(modem.send_raw(command.clone()).then(move |res| {
+                let response = match res.and_then(|pkt| pkt.assert_ok().map(|_| pkt)) {
+                    Ok(r) => r,
+                    Err(e) => return Err(HuaweiError::InitFailed { command, error: Box::new(e) })
+                };
+                results.push(InitStepResult { command, response });
+                Ok(Loop::Continue((modem, init, results)))
+            }))
+        });
+        Box::new(fut)
+    }
+    /// Start talking to the modem at a specified file path, applying the given serial line
+    /// settings and running the given initialisation sequence.
+    ///
+    /// See `new_from_stream_with_init` for more about initialisation sequences.
+    pub fn new_from_path_with_init<P: AsRef<Path>>(path: P, config: &SerialConfig, init: Vec<AtCommand>, h: &Handle) -> HuaweiFuture<(Self, Vec<InitStepResult>)> {
+        let open = || -> HuaweiResult<_> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)?;
+            config.apply(file.as_raw_fd())?;
+            Ok(FileNb::new_nb(file)?.into_io(h)?)
+        };
+        match open() {
+            Ok(ev) => Self::new_from_stream_with_init(ev, init, h),
+            Err(e) => Box::new(futures::future::err(e))
+        }
+    }
+    /// Start talking to a modem, using `open` to (re)open the connection to it, and reopening it
+    /// automatically if it goes away.
+    ///
+    /// `open` is called once immediately (failing this function if it fails), and then again
+    /// every time the modem needs reopening. The results of the initialisation sequence (see
+    /// `ReconnectConfig::init_commands`) are reported through `take_reconnect_rx`; if it fails,
+    /// the modem is closed and reopened, just like after a failed attempt to reopen it. See the
+    /// `supervisor` module for more.
+    pub fn new_supervised<S, F>(mut open: F, config: ReconnectConfig, h: &Handle) -> HuaweiResult<Self> where S: AsyncRead + AsyncWrite + 'static, F: FnMut() -> HuaweiResult<S> + 'static {
+        let framed = AtCodec::default().framed(open()?);
+        let (tx, rx) = mpsc::unbounded();
+        let (evtx, evrx) = mpsc::unbounded();
+        let hub = Arc::new(Mutex::new(UrcHub::default()));
+        let charset = Arc::new(Mutex::new(Charset::default()));
+        let fut = HuaweiModemFuture::new_supervised(framed, open, config, evtx, rx, UrcPublisher::new(hub.clone()), charset.clone());
+        h.spawn(fut.map_err(|e| {
+            error!("HuaweiModemFuture failed: {}", e);
+            error!("Backtrace: {}", e.backtrace());
+        }));
+        Ok(Self::from_parts(tx, hub, charset, Some(evrx)))
+    }
+    /// Start talking to the modem at a specified file path, applying the given serial line
+    /// settings, and reopening it automatically if it goes away (e.g. if the USB device
+    /// re-enumerates).
+    pub fn new_from_path_supervised<P: AsRef<Path>>(path: P, serial: &SerialConfig, config: ReconnectConfig, h: &Handle) -> HuaweiResult<Self> {
+        let path = path.as_ref().to_owned();
+        let serial = *serial;
+        let handle = h.clone();
+        Self::new_supervised(move || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)?;
+            serial.apply(file.as_raw_fd())?;
+            Ok(FileNb::new_nb(file)?.into_io(&handle)?)
+        }, config, h)
     }
     /// Retrieve the URC (Unsolicited Result Code) receiver from the modem (it can only be taken
     /// once).
@@ -104,24 +344,235 @@ impl HuaweiModem {
     /// This can be useful when you configure your modem for message notification on delivery (see
     /// `cmd::sms::set_new_message_indications`), in which case you'll want to check for `CNMI`
     /// URCs through this receiver and use that to poll for new messages.
-    pub fn take_urc_rx(&mut self) -> Option<mpsc::UnboundedReceiver<AtResponse>> {
-        self.urc.take()
+    ///
+    /// Until this is called, only the most recent 64 URCs are kept for the receiver. After that,
+    /// it's unbounded, so make sure you keep reading from it (or drop it); otherwise, use
+    /// `subscribe_urcs`.
+    pub fn take_urc_rx(&self) -> Option<mpsc::UnboundedReceiver<AtResponse>> {
+        self.hub.lock().unwrap().take_receiver()
+    }
+    /// Retrieve the URC (Unsolicited Result Code) receiver as a stream of typed `Urc`s.
+    ///
+    /// This can also only be taken once, but it's separate from `take_urc_rx`, so you can use
+    /// both. It's built on `subscribe_urcs`, so it only gets URCs that arrive after this is
+    /// called; see the `urc` module for more.
+    pub fn take_typed_urc_rx(&self) -> Option<UrcStream> {
+        self.hub.lock().unwrap().take_typed()
+    }
+    /// Subscribe to URCs (Unsolicited Result Codes) from the modem.
+    ///
+    /// Unlike `take_urc_rx`, you can call this as many times as you like: every subscriber gets
+    /// its own copy of each URC matching its `config.prefix`. Each subscriber has a bounded
+    /// buffer, so a slow subscriber will lose URCs rather than using up all your memory; see
+    /// `urc::OverflowPolicy`.
+    pub fn subscribe_urcs(&self, config: SubscriptionConfig) -> UrcSubscription {
+        self.hub.lock().unwrap().subscribe(config)
+    }
+    /// Retrieve the receiver for reconnection events (it can only be taken once).
+    ///
+    /// This only returns anything for modems created with one of the `_supervised` constructors;
+    /// see the `supervisor` module for more.
+    pub fn take_reconnect_rx(&self) -> Option<mpsc::UnboundedReceiver<ReconnectEvent>> {
+        self.reconnect.lock().unwrap().take()
+    }
+    /// Get the modem's current TE character set (see the `charset` module).
+    ///
+    /// The library keeps track of this by watching for `AT+CSCS` commands and responses (e.g. from
+    /// `cmd::general::set_charset` or `cmd::general::get_charset`), and uses it to encode and decode
+    /// strings automatically.
+    pub fn charset(&self) -> Charset {
+        *self.charset.lock().unwrap()
+    }
+    /// Get the modem's capabilities, if they've been probed with `probe_capabilities`.
+    pub fn capabilities(&self) -> Option<ModemCapabilities> {
+        self.capabilities.lock().unwrap().clone()
+    }
+    /// Find out which commands the modem supports (see the `capabilities` module), running the
+    /// test forms of the commands in `capabilities::DEFAULT_TEST_COMMANDS`.
+    ///
+    /// The result is cached, and can be retrieved later with `capabilities`.
+    pub fn probe_capabilities(&self) -> CommandFuture<ModemCapabilities> {
+        let tests = capabilities::DEFAULT_TEST_COMMANDS.iter()
+            .map(|x| x.to_string())
+            .collect();
+        self.probe_capabilities_with(tests)
+    }
+    /// Like `probe_capabilities`, but runs the test forms of the given commands (e.g. `+CNMI`
+    /// or `^SYSCFG`) instead.
+    pub fn probe_capabilities_with(&self, tests: Vec<String>) -> CommandFuture<ModemCapabilities> {
+        let cache = self.capabilities.clone();
+        let fut = capabilities::probe(self, tests)
+            .map(move |caps| {
+                *cache.lock().unwrap() = Some(caps.clone());
+                caps
+            });
+        CommandFuture::new(fut)
+    }
+    /// Get the current settings for the request queue.
+    pub fn queue_config(&self) -> QueueConfig {
+        self.limit.config()
+    }
+    /// Change the settings for the request queue, for this handle and all of its clones.
+    pub fn set_queue_config(&self, config: QueueConfig) {
+        self.limit.set_config(config)
     }
     /// Send a raw AT command to the modem.
-    pub fn send_raw(&mut self, cmd: AtCommand) -> ModemResponseFuture {
-        let (tx, rx) = oneshot::channel();
-        let expected = cmd.expected();
-        let req = ModemRequest {
+    ///
+    /// The command will time out after the duration given by `AtCommand::default_timeout`; use
+    /// `send_raw_with_timeout` if you need to override this.
+    ///
+    /// The command is checked with `AtCommand::validate` first; if it isn't safe to send, the
+    /// future fails with `HuaweiError::UnsafeValue`.
+    pub fn send_raw(&self, cmd: AtCommand) -> ModemResponseFuture {
+        let timeout = cmd.default_timeout();
+        self.send_raw_with_timeout(cmd, timeout)
+    }
+    /// Send a raw AT command to the modem, failing with `HuaweiError::TimedOut` if the modem
+    /// doesn't complete it within `timeout`.
+    ///
+    /// If a command times out, it's abandoned, and the modem moves on to the next command in the
+    /// queue once it's resynchronised with the modem (see `HuaweiError::TimedOut`).
+    pub fn send_raw_with_timeout(&self, cmd: AtCommand, timeout: Duration) -> ModemResponseFuture {
+        self.send_raw_with_options(cmd, Priority::Normal, timeout)
+    }
+    /// Send a raw AT command to the modem with the given priority (see `queue::Priority`).
+    ///
+    /// `send_raw` and `send_raw_with_timeout` use `Priority::Normal`.
+    pub fn send_raw_with_priority(&self, cmd: AtCommand, priority: Priority) -> ModemResponseFuture {
+        let timeout = cmd.default_timeout();
+        self.send_raw_with_options(cmd, priority, timeout)
+    }
+    /// Send a raw AT command to the modem with both the given priority and timeout.
+    ///
+    /// This is what all the other `send_raw` functions use underneath.
+    pub fn send_raw_with_options(&self, cmd: AtCommand, priority: Priority, timeout: Duration) -> ModemResponseFuture {
+        self.send_raw_inner(PendingRequest {
+            expected: cmd.expected(),
             command: cmd,
-            notif: tx,
-            expected
-        };
-        if let Err(_) = self.tx.unbounded_send(req) {
-            ModemResponseFuture { rx: Err(()) }
+            timeout, priority
+        })
+    }
+    /// Run a typed command (see `cmd::AtCommandSpec`), returning its parsed response.
+    ///
+    /// All of the functions in the `cmd` module are implemented using this.
+    pub fn execute<C>(&self, cmd: C) -> CommandFuture<C::Output> where C: AtCommandSpec + Send + 'static {
+        if let Err(e) = cmd.validate() {
+            return CommandFuture::new(futures::future::err(e));
         }
-        else {
-            ModemResponseFuture { rx: Ok(rx) }
+        let fut = self.send_raw_inner(PendingRequest {
+            command: cmd.command(),
+            expected: cmd.expected(),
+            timeout: cmd.timeout(),
+            priority: cmd.priority()
+        }).and_then(move |pkt| cmd.parse(pkt));
+        CommandFuture::new(fut)
+    }
+    fn send_raw_inner(&self, req: PendingRequest) -> ModemResponseFuture {
+        if let Err(e) = req.command.validate() {
+            return ModemResponseFuture { state: ResponseState::Failed(Some(e)) };
         }
+        let state = match self.limit.try_acquire() {
+            Some(slot) => ResponseState::enqueue(&self.tx, req, slot),
+            None => match self.limit.config().when_full {
+                QueueFullPolicy::Error => ResponseState::Failed(Some(HuaweiError::QueueFull)),
+                QueueFullPolicy::Wait => ResponseState::Queueing {
+                    req: Some(req),
+                    tx: self.tx.clone(),
+                    limit: self.limit.clone()
+                }
+            }
+        };
+        ModemResponseFuture { state }
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_core::reactor::Core;
+    use crate::at::{AtValue, AtResultCode};
+    use crate::simulator::ModemSimulator;
+
+    #[test]
+    fn custom_init() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let init = vec![
+            AtCommand::Basic { command: "Z".into(), number: None },
+            AtCommand::Basic { command: "E".into(), number: Some(0) },
+            AtCommand::Equals { param: "+CMEE".into(), value: AtValue::Integer(1) }
+        ];
+        let fut = HuaweiModem::new_from_stream_with_init(sim.stream(), init, &core.handle());
+        let (_modem, results) = core.run(fut).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2].command, AtCommand::Equals { param: "+CMEE".into(), value: AtValue::Integer(1) });
+        assert_eq!(results[2].response.status, AtResultCode::Ok);
+        assert_eq!(sim.received_commands(), vec!["ATZ", "ATE0", "AT+CMEE=1"]);
+
+        let sim = ModemSimulator::new();
+        let init = vec![
+            AtCommand::Basic { command: "E".into(), number: Some(0) },
+            AtCommand::Execute { command: "+BOGUS".into() }
+        ];
+        let fut = HuaweiModem::new_from_stream_with_init(sim.stream(), init, &core.handle());
+        match core.run(fut) {
+            Err(HuaweiError::InitFailed { command, .. }) => {
+                assert_eq!(command, AtCommand::Execute { command: "+BOGUS".into() });
+            },
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("init should have failed")
+        }
+    }
+    #[test]
+    fn command_timeout() {
+        let (mut core, sim, modem) = crate::simulator::setup();
+        sim.script("AT+SLOW", &[]);
+        let slow = AtCommand::Execute { command: "+SLOW".into() };
+        let res = core.run(modem.send_raw_with_timeout(slow.clone(), Duration::from_millis(50)));
+        assert!(matches!(res, Err(HuaweiError::TimedOut)));
+        assert!(core.run(crate::cmd::network::get_signal_quality(&modem)).is_ok());
+        // a late OK for the timed-out command shouldn't be mistaken for the next command's reply
+        sim.script("AT+SLOW", &[]);
+        sim.script("AT+CSCS?", &["OK", "+CSCS: \"IRA\"", "OK"]);
+        let res = core.run(modem.send_raw_with_timeout(slow, Duration::from_millis(50)));
+        assert!(matches!(res, Err(HuaweiError::TimedOut)));
+        let pkt = core.run(modem.send_raw(AtCommand::Execute { command: "+CSQ".into() })).unwrap();
+        assert!(pkt.extract_named_response("+CSQ").is_ok());
+        assert_eq!(sim.received_commands(), vec![
+            "ATE0", "AT+SLOW", "AT+CSCS?", "AT+CSQ", "AT+SLOW", "AT+CSCS?", "AT+CSQ"
+        ]);
+    }
+    #[test]
+    fn priority_and_timeout() {
+        let (mut core, sim, modem) = crate::simulator::setup();
+        sim.script("AT+SLOW", &[]);
+        let csq = modem.send_raw(AtCommand::Execute { command: "+CSQ".into() });
+        let slow = AtCommand::Execute { command: "+SLOW".into() };
+        let slow = modem.send_raw_with_options(slow, Priority::High, Duration::from_millis(50));
+        assert!(matches!(core.run(slow), Err(HuaweiError::TimedOut)));
+        assert!(core.run(csq).is_ok());
+        assert_eq!(sim.received_commands(), vec!["ATE0", "AT+SLOW", "AT+CSCS?", "AT+CSQ"]);
+    }
+    struct GetVersion;
+    impl AtCommandSpec for GetVersion {
+        type Output = String;
+
+        fn command(&self) -> AtCommand {
+            AtCommand::Execute { command: "^GETVER".into() }
+        }
+        fn expected(&self) -> Vec<String> {
+            vec!["^VERSION".into()]
+        }
+        fn parse(&self, pkt: AtResponsePacket) -> HuaweiResult<String> {
+            pkt.decode_named_response("^VERSION")
+        }
+    }
+    #[test]
+    fn execute_custom_command() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        sim.script("AT^GETVER", &["^VERSION: \"11.608\"", "OK"]);
+        assert_eq!(core.run(modem.execute(GetVersion)).unwrap(), "11.608");
+    }
+}
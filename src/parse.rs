@@ -1,20 +1,17 @@
 use crate::at::*;
 use crate::error_codes::CmsError;
 use num::FromPrimitive;
-use encoding::{Encoding, DecoderTrap};
-use encoding::all::{ASCII};
+use crate::charset::latin1_decode;
 use nom::{line_ending, not_line_ending};
 use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
 named!(pub parse_string(&[u8]) -> String,
-       map_res!(
+       map!(
            delimited!(
                tag!("\""),
                take_until!("\""),
                tag!("\"")
            ),
-           |data| {
-               ASCII.decode(data, DecoderTrap::Replace)
-           }
+           latin1_decode
        )
 );
 named!(pub parse_ucs2_string(&[u8]) -> String,
@@ -131,7 +128,7 @@ named!(pub parse_information_response(&[u8]) -> (String, AtValue),
                response: parse_value >>
                (param, response)
            ),
-           |(param, response)| (::std::str::from_utf8(param).unwrap().into(), response)
+           |(param, response)| (latin1_decode(param), response)
        )
 );
 named!(pub parse_response_code(&[u8]) -> AtResultCode,
@@ -177,20 +174,42 @@ named!(pub parse_response_line(&[u8]) -> AtResponse,
                response: r
            }) |
            map_res!(not_line_ending, |s| {
-               let st = ::std::str::from_utf8(s).map_err(|_| ())?.trim();
+               let st = latin1_decode(s);
+               let st = st.trim();
                if st.len() == 0 {
                    return Err(());
                }
-               Ok(AtResponse::Unknown(st.to_string().into()))
+               Ok(AtResponse::Unknown(st.to_string()))
            })
        )
 );
 named!(pub responses(&[u8]) -> Vec<AtResponse>,
+       map!(
+           response_lines,
+           |res| {
+               res.into_iter().map(|(r, _)| r).collect()
+           }
+       )
+);
+fn response_line_with_text(line: &[u8]) -> Option<(AtResponse, &[u8])> {
+    parse_response_line(line).to_result().ok().map(|r| (r, line))
+}
+// Like `responses`, but also returns the text of each line.
+//
+// A "> " at the start of a line is the prompt if nothing else follows it on that line (usually
+// it's the last thing received, since the modem waits for the payload, but a URC can turn up
+// straight after it); otherwise, it's just the start of a line (e.g. a quoted reply in the text
+// of a message).
+named!(pub response_lines<&[u8], Vec<(AtResponse, &[u8])>>,
        map!(
            many1!(
-               terminated!(
-                   opt!(flat_map!(not_line_ending, parse_response_line)),
-                   line_ending
+               alt!(
+                   map!(terminated!(tag!("> "), peek!(alt!(eof!() | line_ending))),
+                        |p| Some((AtResponse::Prompt, p))) |
+                   terminated!(
+                       opt!(map_opt!(not_line_ending, response_line_with_text)),
+                       line_ending
+                   )
                )
            ),
            |res| {
@@ -238,6 +257,27 @@ mod test {
                    (&[] as &[_], AtValue::Unknown("invalid".into())));
     }
     #[test]
+    fn prompt() {
+        assert_eq!(responses(b"\r\n> ").unwrap(),
+                   (&[] as &[_], vec![AtResponse::Prompt]));
+        assert_eq!(responses(b"\r\n+CMGR: \"REC READ\",\"+441234\"\r\n> quoted reply\r\nOK\r\n").unwrap(),
+                   (&[] as &[_], vec![
+                       AtResponse::InformationResponse {
+                           param: "+CMGR".into(),
+                           response: Array(vec![String("REC READ".into()), String("+441234".into())])
+                       },
+                       AtResponse::Unknown("> quoted reply".into()),
+                       AtResponse::ResultCode(AtResultCode::Ok)
+                   ]));
+        assert_eq!(responses(b"\r\n> \r\n^RSSI: 17\r\n").unwrap(),
+                   (&[] as &[_], vec![
+                       AtResponse::Prompt,
+                       AtResponse::InformationResponse { param: "^RSSI".into(), response: Integer(17) }
+                   ]));
+        assert_eq!(responses(b"\r\n+CMS ERROR: 999\r\n").unwrap(),
+                   (&[] as &[_], vec![AtResponse::ResultCode(AtResultCode::CmsErrorUnknown(999))]));
+    }
+    #[test]
     fn value_complex() {
         assert_eq!(
             parse_value(b"3,0,15,\"GSM\",(),(0-3),,(0-1),invalid,(0-2,15),(\"GSM\",\"IRA\")").unwrap(),
@@ -266,4 +306,23 @@ mod test {
             ]))
         )
     }
+    #[test]
+    fn arbitrary_bytes() {
+        let templates: &[&[u8]] = &[
+            b"\r\n+COPS: 0,0,\"Op#ra\",2\r\n",
+            b"\r\n+CP#R: 1,\"123\",129,\"N#me\"\r\nOK\r\n",
+            b"\r\n#\r\n",
+            b"\r\n+CSQ: #,99\r\n"
+        ];
+        for t in templates {
+            for b in 0..=255u8 {
+                let data: Vec<u8> = t.iter().map(|&x| if x == b'#' { b } else { x }).collect();
+                let _ = responses(&data);
+            }
+        }
+        assert_eq!(parse_string(b"\"Op\xE9ra\"").unwrap(),
+                   (&[] as &[_], "Op\u{E9}ra".into()));
+        assert_eq!(parse_response_line(b"\xFF\xFE").unwrap(),
+                   (&[] as &[_], AtResponse::Unknown("\u{FF}\u{FE}".into())));
+    }
 }
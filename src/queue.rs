@@ -0,0 +1,194 @@
+//! Limiting the number of commands waiting to be sent to the modem.
+//!
+//! Every `HuaweiModem` (and all of its clones) share one queue of commands waiting to be sent.
+//! This queue is bounded (see `QueueConfig`), so that a burst of requests can't use up all your
+//! memory; when it's full, new requests either wait for space to become available
+//! (backpressure), or fail with `HuaweiError::QueueFull`.
+//!
+//! Requests are sent in order of their `Priority`, and in the order they were made within each
+//! priority level. If you drop the future for a request before it's been sent to the modem, it's
+//! removed from the queue, and never sent at all.
+use std::sync::{Arc, Mutex};
+use futures::Async;
+use futures::task::{self, Task};
+
+/// The priority of a request (see `HuaweiModem::send_raw_with_priority`).
+///
+/// Higher-priority requests jump ahead of lower-priority ones in the queue, but never interrupt
+/// the command the modem is currently processing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Send after everything else (e.g. for periodic polling).
+    Low,
+    /// The default priority.
+    #[default]
+    Normal,
+    /// Send before everything else (e.g. for hanging up a call).
+    ///
+    /// The modem's initialisation sequence also runs at this priority.
+    High
+}
+/// What to do when a new request is made, but the queue is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    /// Wait until there's space in the queue (i.e. the returned `ModemResponseFuture` won't
+    /// resolve until the command has been queued and run).
+    Wait,
+    /// Fail immediately with `HuaweiError::QueueFull`.
+    Error
+}
+/// Settings for the request queue (see `HuaweiModem::set_queue_config`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// The maximum number of requests that can be waiting to be sent (at least 1).
+    ///
+    /// This doesn't include the command currently being processed by the modem.
+    pub capacity: usize,
+    /// What to do when the queue is full.
+    pub when_full: QueueFullPolicy
+}
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: 32,
+            when_full: QueueFullPolicy::Wait
+        }
+    }
+}
+struct LimitState {
+    config: QueueConfig,
+    queued: usize,
+    waiters: Vec<Task>
+}
+impl LimitState {
+    fn wake(&mut self) {
+        for t in self.waiters.drain(..) {
+            t.notify();
+        }
+    }
+}
+/// Keeps count of the requests in the queue; shared between all the handles and the background
+/// future.
+pub(crate) struct QueueLimit {
+    state: Mutex<LimitState>
+}
+impl QueueLimit {
+    pub(crate) fn new(config: QueueConfig) -> Arc<Self> {
+        Arc::new(QueueLimit {
+            state: Mutex::new(LimitState {
+                config,
+                queued: 0,
+                waiters: vec![]
+            })
+        })
+    }
+    pub(crate) fn config(&self) -> QueueConfig {
+        self.state.lock().unwrap().config
+    }
+    pub(crate) fn set_config(&self, config: QueueConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        state.wake();
+    }
+    /// Try to get a slot in the queue, returning `None` if it's full.
+    ///
+    /// If the background future goes away, all of its requests (and hence their slots) are
+    /// dropped, so anything waiting for a slot will get one, and then find out.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Option<QueueSlot> {
+        let mut state = self.state.lock().unwrap();
+        self.acquire_locked(&mut state)
+    }
+    /// Get a slot in the queue, waiting for one to become available if necessary.
+    pub(crate) fn poll_acquire(self: &Arc<Self>) -> Async<QueueSlot> {
+        let mut state = self.state.lock().unwrap();
+        match self.acquire_locked(&mut state) {
+            Some(slot) => Async::Ready(slot),
+            None => {
+                state.waiters.push(task::current());
+                Async::NotReady
+            }
+        }
+    }
+    fn acquire_locked(self: &Arc<Self>, state: &mut LimitState) -> Option<QueueSlot> {
+        if state.queued >= state.config.capacity.max(1) {
+            return None;
+        }
+        state.queued += 1;
+        Some(QueueSlot { limit: self.clone() })
+    }
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.queued -= 1;
+        state.wake();
+    }
+}
+/// A request's place in the queue; frees up the space when dropped.
+pub(crate) struct QueueSlot {
+    limit: Arc<QueueLimit>
+}
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.limit.release();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Future;
+    use futures::future::join_all;
+    use tokio_core::reactor::Core;
+    use crate::HuaweiModem;
+    use crate::at::AtCommand;
+    use crate::errors::HuaweiError;
+    use crate::simulator::ModemSimulator;
+
+    fn csq() -> AtCommand {
+        AtCommand::Execute { command: "+CSQ".into() }
+    }
+    #[test]
+    fn queue_full() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        modem.set_queue_config(QueueConfig { capacity: 2, when_full: QueueFullPolicy::Error });
+        let other = modem.clone();
+        let a = modem.send_raw(csq());
+        let b = other.send_raw(csq());
+        match modem.send_raw(csq()).wait() {
+            Err(HuaweiError::QueueFull) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("queue should have been full")
+        }
+        core.run(a.join(b)).unwrap();
+        core.run(other.send_raw(csq())).unwrap();
+    }
+    #[test]
+    fn backpressure() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        modem.set_queue_config(QueueConfig { capacity: 1, when_full: QueueFullPolicy::Wait });
+        let reqs = (0..4)
+            .map(|i| modem.send_raw(AtCommand::Equals {
+                param: "+CMEE".into(),
+                value: crate::at::AtValue::Integer(i)
+            }))
+            .collect::<Vec<_>>();
+        core.run(join_all(reqs)).unwrap();
+        assert_eq!(sim.received_commands(), vec!["ATE0", "AT+CMEE=0", "AT+CMEE=1", "AT+CMEE=2", "AT+CMEE=3"]);
+    }
+    #[test]
+    fn priorities_and_cancellation() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        let cmee = |i| AtCommand::Equals { param: "+CMEE".into(), value: crate::at::AtValue::Integer(i) };
+        let low = modem.send_raw_with_priority(cmee(0), Priority::Low);
+        let a = modem.send_raw(cmee(1));
+        let dropped = modem.send_raw(cmee(2));
+        let high = modem.send_raw_with_priority(cmee(3), Priority::High);
+        drop(dropped);
+        core.run(join_all(vec![low, a, high])).unwrap();
+        assert_eq!(sim.received_commands(), vec!["ATE0", "AT+CMEE=3", "AT+CMEE=1", "AT+CMEE=0"]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Configuring the serial line (baud rate, parity, etc.) of a modem's TTY.
+//!
+//! USB modems usually don't care much about the baud rate you set, but they *do* care about
+//! the TTY being in raw mode - if some other program has left it in canonical or echo mode,
+//! the kernel's line discipline will mangle everything before it gets to us. Opening a modem with
+//! `HuaweiModem::new_from_path_with_config` applies a `SerialConfig` to the TTY before using it,
+//! which avoids this sort of thing.
+use std::io;
+use std::os::unix::io::RawFd;
+use termios::*;
+use termios::os::target::{B57600, B115200, B230400, CRTSCTS};
+use crate::errors::HuaweiResult;
+
+/// The number of data bits per character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataBits {
+    /// Five data bits.
+    Five,
+    /// Six data bits.
+    Six,
+    /// Seven data bits.
+    Seven,
+    /// Eight data bits.
+    Eight
+}
+/// The parity checking mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit.
+    None,
+    /// Odd parity.
+    Odd,
+    /// Even parity.
+    Even
+}
+/// The number of stop bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit.
+    One,
+    /// Two stop bits.
+    Two
+}
+/// The flow control mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No flow control.
+    None,
+    /// Hardware flow control, using the RTS/CTS lines.
+    RtsCts,
+    /// Software flow control, using XON/XOFF characters.
+    ///
+    /// Only use this if everything sent over the line is text: the XON/XOFF characters can turn
+    /// up in binary data (e.g. if the modem's been switched out of AT command mode), and would
+    /// get swallowed.
+    XonXoff
+}
+/// Serial line settings to apply to a TTY when opening a modem.
+///
+/// The `Default` value of this `struct` (115200 baud, 8N1, no flow control, raw mode) should work
+/// for basically every USB modem out there.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SerialConfig {
+    /// The baud rate.
+    ///
+    /// Only the standard rates from 50 to 230400 baud are supported.
+    pub baud_rate: u32,
+    /// The number of data bits.
+    pub data_bits: DataBits,
+    /// The parity checking mode.
+    pub parity: Parity,
+    /// The number of stop bits.
+    pub stop_bits: StopBits,
+    /// The flow control mode.
+    pub flow_control: FlowControl,
+    /// Whether to put the TTY into raw mode (no echo, no canonical line processing, no
+    /// translation of line endings).
+    ///
+    /// You almost certainly want this set to `true`.
+    pub raw: bool
+}
+impl Default for SerialConfig {
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            raw: true
+        }
+    }
+}
+impl SerialConfig {
+    fn speed(&self) -> io::Result<speed_t> {
+        let ret = match self.baud_rate {
+            50 => B50,
+            75 => B75,
+            110 => B110,
+            134 => B134,
+            150 => B150,
+            200 => B200,
+            300 => B300,
+            600 => B600,
+            1200 => B1200,
+            1800 => B1800,
+            2400 => B2400,
+            4800 => B4800,
+            9600 => B9600,
+            19200 => B19200,
+            38400 => B38400,
+            57600 => B57600,
+            115200 => B115200,
+            230400 => B230400,
+            x => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported baud rate {}", x)))
+        };
+        Ok(ret)
+    }
+    /// Apply these settings to the TTY referred to by the file descriptor `fd`.
+    ///
+    /// Any data pending in the TTY's input and output buffers is discarded.
+    pub fn apply(&self, fd: RawFd) -> HuaweiResult<()> {
+        let mut tio = Termios::from_fd(fd)?;
+        self.configure(&mut tio)?;
+        tcsetattr(fd, TCSANOW, &tio)?;
+        tcflush(fd, TCIOFLUSH)?;
+        Ok(())
+    }
+    /// Change the settings in `tio` to match these ones.
+    fn configure(&self, tio: &mut Termios) -> io::Result<()> {
+        if self.raw {
+            cfmakeraw(tio);
+        }
+        cfsetspeed(tio, self.speed()?)?;
+        tio.c_cflag &= !CSIZE;
+        tio.c_cflag |= match self.data_bits {
+            DataBits::Five => CS5,
+            DataBits::Six => CS6,
+            DataBits::Seven => CS7,
+            DataBits::Eight => CS8
+        };
+        match self.parity {
+            Parity::None => {
+                tio.c_cflag &= !(PARENB | PARODD);
+                tio.c_iflag &= !INPCK;
+            },
+            Parity::Odd => {
+                tio.c_cflag |= PARENB | PARODD;
+                tio.c_iflag |= INPCK;
+            },
+            Parity::Even => {
+                tio.c_cflag |= PARENB;
+                tio.c_cflag &= !PARODD;
+                tio.c_iflag |= INPCK;
+            }
+        }
+        match self.stop_bits {
+            StopBits::One => tio.c_cflag &= !CSTOPB,
+            StopBits::Two => tio.c_cflag |= CSTOPB
+        }
+        tio.c_cflag &= !CRTSCTS;
+        tio.c_iflag &= !(IXON | IXOFF | IXANY);
+        match self.flow_control {
+            FlowControl::None => {},
+            FlowControl::RtsCts => tio.c_cflag |= CRTSCTS,
+            FlowControl::XonXoff => tio.c_iflag |= IXON | IXOFF
+        }
+        tio.c_cflag |= CREAD | CLOCAL;
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn speeds() {
+        let speed = |baud_rate| SerialConfig { baud_rate, ..Default::default() }.speed().ok();
+        assert_eq!(speed(50), Some(B50));
+        assert_eq!(speed(9600), Some(B9600));
+        assert_eq!(speed(57600), Some(B57600));
+        assert_eq!(speed(115200), Some(B115200));
+        assert_eq!(speed(230400), Some(B230400));
+        assert_eq!(speed(0), None);
+        assert_eq!(speed(12345), None);
+        assert_eq!(speed(460800), None);
+    }
+    #[test]
+    fn termios_flags() {
+        // the master end of a pseudoterminal is as good a TTY as any
+        let pty = OpenOptions::new().read(true).write(true).open("/dev/ptmx").unwrap();
+        let configure = |config: SerialConfig| {
+            let mut tio = Termios::from_fd(pty.as_raw_fd()).unwrap();
+            tio.c_cflag |= PARENB | PARODD | CRTSCTS | CSTOPB;
+            tio.c_iflag |= INPCK | IXON | IXOFF | IXANY;
+            tio.c_lflag |= ICANON | ECHO;
+            config.configure(&mut tio).unwrap();
+            tio
+        };
+
+        let tio = configure(SerialConfig::default());
+        assert_eq!(cfgetospeed(&tio), B115200);
+        assert_eq!(tio.c_cflag & CSIZE, CS8);
+        assert_eq!(tio.c_cflag & (PARENB | PARODD | CSTOPB | CRTSCTS), 0);
+        assert_eq!(tio.c_cflag & (CREAD | CLOCAL), CREAD | CLOCAL);
+        assert_eq!(tio.c_iflag & (INPCK | IXON | IXOFF | IXANY), 0);
+        assert_eq!(tio.c_lflag & (ICANON | ECHO), 0);
+
+        let tio = configure(SerialConfig { parity: Parity::Odd, ..Default::default() });
+        assert_eq!(tio.c_cflag & (PARENB | PARODD), PARENB | PARODD);
+        assert_eq!(tio.c_iflag & INPCK, INPCK);
+        let tio = configure(SerialConfig { parity: Parity::Even, ..Default::default() });
+        assert_eq!(tio.c_cflag & (PARENB | PARODD), PARENB);
+        assert_eq!(tio.c_iflag & INPCK, INPCK);
+
+        let tio = configure(SerialConfig { flow_control: FlowControl::RtsCts, ..Default::default() });
+        assert_eq!(tio.c_cflag & CRTSCTS, CRTSCTS);
+        assert_eq!(tio.c_iflag & (IXON | IXOFF), 0);
+        let tio = configure(SerialConfig { flow_control: FlowControl::XonXoff, ..Default::default() });
+        assert_eq!(tio.c_cflag & CRTSCTS, 0);
+        assert_eq!(tio.c_iflag & (IXON | IXOFF | IXANY), IXON | IXOFF);
+
+        let tio = configure(SerialConfig {
+            data_bits: DataBits::Seven,
+            stop_bits: StopBits::Two,
+            raw: false,
+            ..Default::default()
+        });
+        assert_eq!(tio.c_cflag & CSIZE, CS7);
+        assert_eq!(tio.c_cflag & CSTOPB, CSTOPB);
+        assert_eq!(tio.c_lflag & (ICANON | ECHO), ICANON | ECHO);
+    }
+}
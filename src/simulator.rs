@@ -0,0 +1,679 @@
+//! A scriptable, in-process modem simulator, for testing.
+//!
+//! `ModemSimulator` pretends to be a (rather simple) AT modem. It keeps enough state to respond
+//! sensibly to the commands wrapped in the `cmd` module - SMS storage (`AT+CMGL`, `AT+CMGD`,
+//! `AT+CMGS`), network registration, PIN entry, signal quality and character sets - and lets you inject URCs
+//! and script error responses, so you can test your code end to end without a real dongle.
+//!
+//! To use it, make a `ModemSimulator`, and hand the transport returned by its `stream` method to
+//! `HuaweiModem::new_from_stream`:
+//!
+//! ```rust,no_run
+//! # use huawei_modem::HuaweiModem;
+//! # use huawei_modem::simulator::ModemSimulator;
+//! # use tokio_core::reactor::Core;
+//! let mut core = Core::new().unwrap();
+//! let sim = ModemSimulator::new();
+//! let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+//! sim.script("AT+CMGS", &["+CMS ERROR: 331"]);
+//! ```
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+use crate::charset::Charset;
+use crate::cmd::network::{RegistrationState, SignalQuality, PinState};
+use crate::cmd::sms::MessageStatus;
+use crate::pdu::{HexData};
+use crate::util::HuaweiFromPrimitive;
+
+/// A message sent through the simulator with `AT+CMGS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SentMessage {
+    /// A message sent in PDU mode. Contains the raw PDU bytes (including the SMSC address).
+    Pdu(Vec<u8>),
+    /// A message sent in text mode.
+    ///
+    /// The recipient and text are decoded from the character set in use when the message was sent.
+    Text {
+        /// The recipient given in the command.
+        to: String,
+        /// The message text.
+        text: String
+    }
+}
+struct StoredMessage {
+    status: MessageStatus,
+    pdu: Vec<u8>
+}
+struct PendingMessage {
+    command: String,
+    to: Option<String>
+}
+struct SimState {
+    input: Vec<u8>,
+    output: VecDeque<u8>,
+    read_task: Option<Task>,
+    hung_up: bool,
+    echo: bool,
+    registration: RegistrationState,
+    signal: SignalQuality,
+    pin: Option<String>,
+    pin_entered: bool,
+    text_mode: bool,
+    charset: Charset,
+    messages: BTreeMap<u32, StoredMessage>,
+    sent: Vec<SentMessage>,
+    next_reference: u32,
+    pending: Option<PendingMessage>,
+    /// Whether to hold back the `> ` prompt for `AT+CMGS`.
+    hold_prompt: bool,
+    /// Whether a `> ` prompt is being held back.
+    prompt_held: bool,
+    cancelled: Vec<String>,
+    scripts: HashMap<String, VecDeque<Vec<String>>>,
+    commands: Vec<String>
+}
+impl SimState {
+    fn emit_line(&mut self, line: &str) {
+        self.output.extend(b"\r\n");
+        self.output.extend(line.as_bytes());
+        self.output.extend(b"\r\n");
+    }
+    fn wake(&mut self) {
+        if let Some(t) = self.read_task.take() {
+            t.notify();
+        }
+    }
+    fn decode_text(&self, text: &str) -> String {
+        match self.charset {
+            Charset::Gsm | Charset::Ucs2 => self.charset.decode(text.as_bytes()),
+            _ => text.to_string()
+        }
+    }
+    fn take_script(&mut self, line: &str) -> Option<Vec<String>> {
+        let upper = line.to_uppercase();
+        let key = self.scripts.keys()
+            .filter(|k| upper.starts_with(*k))
+            .max_by_key(|k| k.len())
+            .cloned()?;
+        let ret = self.scripts.get_mut(&key).and_then(|q| q.pop_front());
+        if self.scripts.get(&key).map(|q| q.is_empty()).unwrap_or(false) {
+            self.scripts.remove(&key);
+        }
+        ret
+    }
+    fn process_input(&mut self) {
+        loop {
+            if self.pending.is_some() {
+                if self.prompt_held {
+                    return;
+                }
+                let end = self.input.iter().position(|&b| b == 0x1A || b == 0x1B);
+                let end = match end {
+                    Some(e) => e,
+                    None => return
+                };
+                let body: Vec<u8> = self.input.drain(..=end).collect();
+                let pending = self.pending.take().unwrap();
+                if body[end] == 0x1B {
+                    debug!("simulator: message cancelled");
+                    self.cancelled.push(String::from_utf8_lossy(&body[..end]).trim().to_string());
+                    self.emit_line("OK");
+                    continue;
+                }
+                let body = String::from_utf8_lossy(&body[..end]).trim().to_string();
+                self.finish_message(pending, body);
+                continue;
+            }
+            let end = match self.input.iter().position(|&b| b == b'\r' || b == b'\n') {
+                Some(e) => e,
+                None => return
+            };
+            let line: Vec<u8> = self.input.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            self.process_line(line);
+        }
+    }
+    fn finish_message(&mut self, pending: PendingMessage, body: String) {
+        if let Some(lines) = self.take_script(&pending.command) {
+            for line in lines {
+                self.emit_line(&line);
+            }
+            return;
+        }
+        let msg = match pending.to {
+            Some(to) => SentMessage::Text {
+                to: self.decode_text(&to),
+                text: self.decode_text(&body)
+            },
+            None => match HexData::decode(&body) {
+                Ok(pdu) => SentMessage::Pdu(pdu),
+                Err(_) => {
+                    self.emit_line("+CMS ERROR: 304");
+                    return;
+                }
+            }
+        };
+        debug!("simulator: sent message {:?}", msg);
+        self.sent.push(msg);
+        let mr = self.next_reference;
+        self.next_reference = (self.next_reference + 1) % 256;
+        self.emit_line(&format!("+CMGS: {}", mr));
+        self.emit_line("OK");
+    }
+    fn process_line(&mut self, line: String) {
+        debug!("simulator: got line {:?}", line);
+        if self.echo {
+            self.output.extend(line.as_bytes());
+            self.output.extend(b"\r\n");
+        }
+        if line.len() < 2 || !line.as_bytes()[..2].eq_ignore_ascii_case(b"AT") {
+            self.emit_line("ERROR");
+            return;
+        }
+        self.commands.push(line.clone());
+        let cmd = line[2..].to_string();
+        let upper = cmd.to_uppercase();
+        if upper.starts_with("+CMGS=") {
+            let arg = &cmd[6..];
+            let to = if self.text_mode {
+                Some(arg.trim_matches('"').to_string())
+            }
+            else {
+                None
+            };
+            self.pending = Some(PendingMessage { command: line.clone(), to });
+            if self.hold_prompt {
+                self.prompt_held = true;
+            }
+            else {
+                self.send_prompt();
+            }
+            return;
+        }
+        if let Some(lines) = self.take_script(&line) {
+            for line in lines {
+                self.emit_line(&line);
+            }
+            return;
+        }
+        let result = self.dispatch(&upper, &cmd);
+        match result {
+            Ok(lines) => {
+                for line in lines {
+                    self.emit_line(&line);
+                }
+                self.emit_line("OK");
+            },
+            Err(e) => self.emit_line(&e)
+        }
+    }
+    fn send_prompt(&mut self) {
+        // Like some real firmwares, throw away anything that arrived before the prompt (unless
+        // it was held back, in which case the TE might well have given up on it already).
+        if !self.prompt_held {
+            self.input.clear();
+        }
+        self.prompt_held = false;
+        self.output.extend(b"\r\n> ");
+    }
+    fn pin_locked(&self) -> bool {
+        self.pin.is_some() && !self.pin_entered
+    }
+    fn dispatch(&mut self, upper: &str, cmd: &str) -> Result<Vec<String>, String> {
+        let arg = cmd.find('=').map(|i| &cmd[(i + 1)..]).unwrap_or("");
+        let ints = || -> Result<Vec<u32>, String> {
+            arg.split(',')
+                .map(|x| x.trim().parse().map_err(|_| "ERROR".to_string()))
+                .collect()
+        };
+        match upper {
+            "" | "Z" => Ok(vec![]),
+            "E" | "E0" | "E1" => {
+                self.echo = upper == "E1";
+                Ok(vec![])
+            },
+            "+CSQ" => Ok(vec![format!("+CSQ: {},{}", self.signal.rssi, self.signal.ber)]),
+            "+CREG?" => Ok(vec![format!("+CREG: 0,{}", self.registration as u8)]),
+            "+CFUN?" => Ok(vec!["+CFUN: 1".into()]),
+            "+CPIN?" => {
+                let st = if self.pin_locked() { "SIM PIN" } else { "READY" };
+                Ok(vec![format!("+CPIN: {}", st)])
+            },
+            "+CMGF?" => Ok(vec![format!("+CMGF: {}", if self.text_mode { 1 } else { 0 })]),
+            "+CSCS?" => Ok(vec![format!("+CSCS: \"{}\"", self.charset)]),
+            x if x.starts_with("+CSCS=") => {
+                self.charset = Charset::from_name(arg.trim_matches('"'))
+                    .ok_or_else(|| "ERROR".to_string())?;
+                Ok(vec![])
+            },
+            x if x.starts_with("+CPIN=") => {
+                let given = arg.trim_matches('"');
+                match self.pin {
+                    Some(ref p) if !self.pin_entered => {
+                        if p == given {
+                            self.pin_entered = true;
+                            Ok(vec![])
+                        }
+                        else {
+                            Err("+CME ERROR: 16".into())
+                        }
+                    },
+                    _ => Err("+CME ERROR: 3".into())
+                }
+            },
+            x if x.starts_with("+CMGF=") => {
+                self.text_mode = ints()?.first() == Some(&1);
+                Ok(vec![])
+            },
+            x if x.starts_with("+CNMI=") || x.starts_with("+CSCA=") || x.starts_with("+CMEE=") => {
+                Ok(vec![])
+            },
+            x if x.starts_with("+CMGL=") => {
+                if self.pin_locked() {
+                    return Err("+CMS ERROR: 311".into());
+                }
+                if self.text_mode {
+                    return Err("+CMS ERROR: 305".into());
+                }
+                let stat = *ints()?.first().ok_or_else(|| "ERROR".to_string())?;
+                let stat = MessageStatus::from_integer(stat)
+                    .map_err(|_| "+CMS ERROR: 304".to_string())?;
+                let mut ret = vec![];
+                for (idx, msg) in self.messages.iter_mut() {
+                    if stat != MessageStatus::All && msg.status != stat {
+                        continue;
+                    }
+                    ret.push(format!("+CMGL: {},{},,{}", idx, msg.status as u8, msg.pdu.len()));
+                    ret.push(format!("{}", HexData(&msg.pdu)));
+                    if msg.status == MessageStatus::ReceivedUnread {
+                        msg.status = MessageStatus::ReceivedRead;
+                    }
+                }
+                Ok(ret)
+            },
+            x if x.starts_with("+CMGD=") => {
+                let args = ints()?;
+                let index = *args.first().ok_or_else(|| "ERROR".to_string())?;
+                let flag = args.get(1).cloned().unwrap_or(0);
+                if flag == 0 {
+                    if self.messages.remove(&index).is_none() {
+                        return Err("+CMS ERROR: 321".into());
+                    }
+                    return Ok(vec![]);
+                }
+                self.messages.retain(|_, msg| {
+                    use self::MessageStatus::*;
+                    !matches!((flag, msg.status),
+                        (1, ReceivedRead)
+                        | (2, ReceivedRead) | (2, StoredSent)
+                        | (3, ReceivedRead) | (3, StoredSent) | (3, StoredUnsent)
+                        | (4, _))
+                });
+                Ok(vec![])
+            },
+            _ => Err("ERROR".into())
+        }
+    }
+}
+/// A simulated modem.
+///
+/// This is a cheaply cloneable handle to the simulator's state; use it to set up the
+/// simulator, and inspect what your code did to it afterwards.
+#[derive(Clone)]
+pub struct ModemSimulator {
+    state: Arc<Mutex<SimState>>
+}
+impl Default for ModemSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ModemSimulator {
+    /// Make a new simulator, in its default state: registered on the home network, good signal,
+    /// no PIN set, PDU mode, with an empty message store.
+    pub fn new() -> Self {
+        let state = SimState {
+            input: vec![],
+            output: VecDeque::new(),
+            read_task: None,
+            hung_up: false,
+            echo: true,
+            registration: RegistrationState::RegisteredHome,
+            signal: SignalQuality { rssi: 20, ber: 99 },
+            pin: None,
+            pin_entered: false,
+            text_mode: false,
+            charset: Charset::default(),
+            messages: BTreeMap::new(),
+            sent: vec![],
+            next_reference: 0,
+            pending: None,
+            hold_prompt: false,
+            prompt_held: false,
+            cancelled: vec![],
+            scripts: HashMap::new(),
+            commands: vec![]
+        };
+        Self { state: Arc::new(Mutex::new(state)) }
+    }
+    /// Get a transport connected to this simulator, for use with `HuaweiModem::new_from_stream`.
+    pub fn stream(&self) -> SimulatorStream {
+        SimulatorStream { state: self.state.clone() }
+    }
+    /// Set the registration state reported by `AT+CREG?`.
+    pub fn set_registration(&self, reg: RegistrationState) {
+        self.state.lock().unwrap().registration = reg;
+    }
+    /// Set the signal quality reported by `AT+CSQ`.
+    pub fn set_signal_quality(&self, sq: SignalQuality) {
+        self.state.lock().unwrap().signal = sq;
+    }
+    /// Lock the SIM with a PIN (or unlock it, if `None` is given).
+    ///
+    /// The PIN will need to be entered with `AT+CPIN` before the message store can be used.
+    pub fn set_pin(&self, pin: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        state.pin = pin.map(|x| x.to_string());
+        state.pin_entered = false;
+    }
+    /// Get the current PIN state of the simulated SIM.
+    pub fn pin_state(&self) -> PinState {
+        if self.state.lock().unwrap().pin_locked() {
+            PinState::SimPin
+        }
+        else {
+            PinState::Ready
+        }
+    }
+    /// Put a message in the simulator's message store, returning its index.
+    pub fn store_message(&self, status: MessageStatus, pdu: &[u8]) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        let index = state.messages.keys().next_back().map(|x| x + 1).unwrap_or(0);
+        state.messages.insert(index, StoredMessage { status, pdu: pdu.to_vec() });
+        index
+    }
+    /// Get the indices and statuses of all messages in the message store.
+    pub fn stored_messages(&self) -> Vec<(u32, MessageStatus)> {
+        self.state.lock().unwrap().messages.iter()
+            .map(|(idx, msg)| (*idx, msg.status))
+            .collect()
+    }
+    /// Get all of the messages sent through the simulator so far.
+    pub fn sent_messages(&self) -> Vec<SentMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+    /// Get the text received before the ESC for each `AT+CMGS` that was aborted with ESC (which
+    /// should be empty, if the TE waited for the prompt like it should have).
+    pub fn cancelled_messages(&self) -> Vec<String> {
+        self.state.lock().unwrap().cancelled.clone()
+    }
+    /// Hold back the `> ` prompt for `AT+CMGS` (if `hold` is `true`), or send any prompt that's
+    /// been held back, and stop holding them back (if `hold` is `false`).
+    ///
+    /// While a prompt is being held back, the simulator doesn't process anything the TE sends.
+    pub fn hold_prompt(&self, hold: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.hold_prompt = hold;
+        if !hold && state.prompt_held {
+            state.send_prompt();
+            state.process_input();
+            state.wake();
+        }
+    }
+    /// Get all of the commands received by the simulator so far, as they were sent.
+    pub fn received_commands(&self) -> Vec<String> {
+        self.state.lock().unwrap().commands.clone()
+    }
+    /// Send an unsolicited line (e.g. `+CMTI: "SM",3`) to the TE.
+    pub fn inject_urc(&self, line: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.emit_line(line);
+        state.wake();
+    }
+    /// Script the response to the next command starting with `prefix` (e.g. `AT+CMGS`).
+    ///
+    /// The next matching command will get `lines` as its response, instead of whatever the
+    /// simulator would have said; the last line should therefore be a result code, like `OK` or
+    /// `+CMS ERROR: 500`. Scripting the same prefix multiple times queues up responses.
+    pub fn script(&self, prefix: &str, lines: &[&str]) {
+        let lines = lines.iter().map(|x| x.to_string()).collect();
+        self.state.lock().unwrap().scripts
+            .entry(prefix.to_uppercase())
+            .or_default()
+            .push_back(lines);
+    }
+    /// Simulate the modem going away (e.g. being unplugged): the transport will report
+    /// end-of-file, and writes to it will fail.
+    pub fn hang_up(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.hung_up = true;
+        state.wake();
+    }
+}
+/// The transport end of a `ModemSimulator`.
+pub struct SimulatorStream {
+    state: Arc<Mutex<SimState>>
+}
+impl Read for SimulatorStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.output.is_empty() {
+            if state.hung_up {
+                return Ok(0);
+            }
+            state.read_task = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let len = ::std::cmp::min(buf.len(), state.output.len());
+        for (i, b) in state.output.drain(..len).enumerate() {
+            buf[i] = b;
+        }
+        Ok(len)
+    }
+}
+impl Write for SimulatorStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.hung_up {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.input.extend(buf);
+        state.process_input();
+        if !state.output.is_empty() {
+            state.wake();
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl AsyncRead for SimulatorStream {}
+impl AsyncWrite for SimulatorStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+/// Make a `ModemSimulator`, and a `HuaweiModem` talking to it (for tests).
+#[cfg(test)]
+pub(crate) fn setup() -> (tokio_core::reactor::Core, ModemSimulator, crate::HuaweiModem) {
+    let core = tokio_core::reactor::Core::new().unwrap();
+    let sim = ModemSimulator::new();
+    let modem = crate::HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+    (core, sim, modem)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::HuaweiModem;
+    use crate::at::AtResultCode;
+    use crate::cmd;
+    use crate::error_codes::CmsError;
+    use crate::errors::HuaweiError;
+    use futures::Stream;
+    use tokio_core::reactor::Core;
+
+    const DELIVER_PDU: &str = "07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07";
+
+    #[test]
+    fn network_commands() {
+        let (mut core, sim, modem) = setup();
+        sim.set_signal_quality(SignalQuality { rssi: 14, ber: 99 });
+        let sq = core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        assert_eq!(sq, SignalQuality { rssi: 14, ber: 99 });
+        sim.set_registration(RegistrationState::RegisteredRoaming);
+        let reg = core.run(cmd::network::get_registration(&modem)).unwrap();
+        assert_eq!(reg, RegistrationState::RegisteredRoaming);
+    }
+    #[test]
+    fn pin_entry() {
+        let (mut core, sim, modem) = setup();
+        sim.set_pin(Some("1234"));
+        let st = core.run(cmd::network::get_pin_state(&modem)).unwrap();
+        assert_eq!(st, PinState::SimPin);
+        let res = core.run(cmd::network::input_pin(&modem, "0000".into()));
+        assert!(res.is_err());
+        core.run(cmd::network::input_pin(&modem, "1234".into())).unwrap();
+        assert_eq!(sim.pin_state(), PinState::Ready);
+    }
+    #[test]
+    fn list_and_delete() {
+        let (mut core, sim, modem) = setup();
+        let pdu = HexData::decode(DELIVER_PDU).unwrap();
+        sim.store_message(MessageStatus::ReceivedUnread, &pdu);
+        sim.store_message(MessageStatus::ReceivedUnread, &pdu);
+        let msgs = core.run(cmd::sms::list_sms_pdu(&modem, MessageStatus::All)).unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[1].index, 1);
+        assert_eq!(msgs[0].raw_pdu, pdu);
+        core.run(cmd::sms::del_sms_pdu(&modem, cmd::sms::DeletionOptions::Indexed(0))).unwrap();
+        assert_eq!(sim.stored_messages(), vec![(1, MessageStatus::ReceivedRead)]);
+    }
+    #[test]
+    fn send_and_script_errors() {
+        use crate::pdu::{Pdu, PduAddress};
+        use crate::gsm_encoding::GsmMessageData;
+
+        let (mut core, sim, modem) = setup();
+        let addr: PduAddress = "+441234567890".parse().unwrap();
+        let data = GsmMessageData::encode_message("hello").pop().unwrap();
+        let pdu = Pdu::make_simple_message(addr, data);
+        let mr = core.run(cmd::sms::send_sms_pdu(&modem, &pdu)).unwrap();
+        assert_eq!(mr, 0);
+        assert_eq!(sim.sent_messages(), vec![SentMessage::Pdu(pdu.as_bytes().0)]);
+        sim.script("AT+CMGS", &["+CMS ERROR: 331"]);
+        match core.run(cmd::sms::send_sms_pdu(&modem, &pdu)) {
+            Err(HuaweiError::AtError(AtResultCode::CmsError(CmsError::NoNetworkService))) => {},
+            x => panic!("unexpected result: {:?}", x)
+        }
+    }
+    #[test]
+    fn send_textmode() {
+        let (mut core, sim, modem) = setup();
+        core.run(cmd::sms::set_sms_textmode(&modem, true)).unwrap();
+        core.run(cmd::sms::send_sms_textmode(&modem, "+441234567890".into(), "hi".into())).unwrap();
+        assert_eq!(sim.sent_messages(), vec![SentMessage::Text {
+            to: "+441234567890".into(),
+            text: "hi".into()
+        }]);
+    }
+    #[test]
+    fn cancel_before_prompt() {
+        use std::time::Duration;
+        use futures::Future;
+        use futures_timer::Delay;
+        use crate::at::{AtCommand, AtValue};
+
+        let (mut core, sim, modem) = setup();
+        core.run(cmd::sms::set_sms_textmode(&modem, true)).unwrap();
+        sim.hold_prompt(true);
+        // drop the request before the prompt turns up...
+        let send = cmd::sms::send_sms_textmode(&modem, "+4412".into(), "hi".into());
+        match core.run(send.select2(Delay::new(Duration::from_millis(50)))) {
+            Ok(futures::future::Either::B(_)) => {},
+            _ => panic!("message shouldn't have been sent")
+        }
+        assert_eq!(sim.received_commands().last().unwrap(), "AT+CMGS=\"+4412\"");
+        sim.hold_prompt(false);
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        assert_eq!(sim.cancelled_messages(), vec![""]);
+
+        // ...or let it time out
+        sim.hold_prompt(true);
+        let cmgs = AtCommand::WithPrompt {
+            command: Box::new(AtCommand::Equals {
+                param: "+CMGS".into(),
+                value: AtValue::String("+4412".into())
+            }),
+            payload: "hi".into()
+        };
+        let res = core.run(modem.send_raw_with_timeout(cmgs, Duration::from_millis(50)));
+        assert!(matches!(res, Err(HuaweiError::TimedOut)));
+        sim.hold_prompt(false);
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        assert_eq!(sim.cancelled_messages(), vec!["", ""]);
+        assert!(sim.sent_messages().is_empty());
+    }
+    #[test]
+    fn charsets() {
+        let (mut core, sim, modem) = setup();
+        core.run(cmd::general::set_charset(&modem, Charset::Ucs2)).unwrap();
+        assert_eq!(modem.charset(), Charset::Ucs2);
+        assert_eq!(core.run(cmd::general::get_charset(&modem)).unwrap(), Charset::Ucs2);
+        core.run(cmd::sms::set_sms_textmode(&modem, true)).unwrap();
+        core.run(cmd::sms::send_sms_textmode(&modem, "+4412".into(), "h\u{E9}llo \u{263A}".into())).unwrap();
+        assert_eq!(sim.sent_messages(), vec![SentMessage::Text {
+            to: "+4412".into(),
+            text: "h\u{E9}llo \u{263A}".into()
+        }]);
+        assert!(core.run(cmd::general::set_charset(&modem, Charset::Gsm)).is_ok());
+        sim.script("AT+CSCS?", &["+CSCS: \"IRA\"", "OK"]);
+        assert_eq!(core.run(cmd::general::get_charset(&modem)).unwrap(), Charset::Ira);
+        assert_eq!(modem.charset(), Charset::Ira);
+    }
+    #[test]
+    fn unsafe_values() {
+        let (mut core, sim, modem) = setup();
+        core.run(cmd::sms::set_sms_textmode(&modem, true)).unwrap();
+        let res = core.run(cmd::sms::send_sms_textmode(&modem, "+44\"\r\nAT+CFUN=0".into(), "hi".into()));
+        assert!(matches!(res, Err(HuaweiError::UnsafeValue { .. })));
+        let res = core.run(cmd::sms::send_sms_textmode(&modem, "+4412".into(), "hi\x1AAT+CFUN=0\r".into()));
+        assert!(matches!(res, Err(HuaweiError::UnsafeValue { .. })));
+        let res = core.run(cmd::network::input_pin(&modem, "1234\"".into()));
+        assert!(matches!(res, Err(HuaweiError::UnsafeValue { .. })));
+        assert!(sim.sent_messages().is_empty());
+        assert_eq!(sim.received_commands(), vec!["ATE0", "AT+CMGF=1"]);
+    }
+    #[test]
+    fn non_ascii_input() {
+        let sim = ModemSimulator::new();
+        let mut stream = sim.stream();
+        stream.write_all(b"\xE9T\r\nAT\xE9\r\n").unwrap();
+        let mut out = vec![0; 64];
+        let len = stream.read(&mut out).unwrap();
+        let out = String::from_utf8_lossy(&out[..len]);
+        assert_eq!(out.matches("\r\nERROR\r\n").count(), 2);
+    }
+    #[test]
+    fn urc_injection() {
+        let (mut core, sim, modem) = setup();
+        let urcs = modem.take_urc_rx().unwrap();
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        sim.inject_urc("+CMTI: \"SM\",3");
+        let (urc, _) = core.run(urcs.into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(urc, Some(crate::at::AtResponse::InformationResponse {
+            param: "+CMTI".into(),
+            response: crate::at::AtValue::Array(vec![
+                crate::at::AtValue::String("SM".into()),
+                crate::at::AtValue::Integer(3)
+            ])
+        }));
+    }
+}
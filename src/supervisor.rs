@@ -0,0 +1,205 @@
+//! Automatic reconnection to modems that go away.
+//!
+//! USB modems have a habit of disappearing and re-enumerating - after a `AT+CFUN=1,1` restart,
+//! after a firmware crash, or after a power blip. Normally, this makes the background future
+//! exit, and the `HuaweiModem` becomes useless. If you create your modem with
+//! `HuaweiModem::new_supervised` or `HuaweiModem::new_from_path_supervised`, the background future
+//! will instead try to reopen the modem (with exponential backoff), replay an initialisation
+//! sequence, and carry on as if nothing had happened.
+//!
+//! While the modem is disconnected, new commands are queued up, and will be sent once the modem
+//! comes back. The command in progress when the modem went away fails with
+//! `HuaweiError::Disconnected`.
+//!
+//! Each time the modem is (re)opened, the initialisation sequence has to succeed before the modem
+//! counts as connected; if any step of it fails, the attempt counts as failed, and the modem is
+//! closed and tried again after a backoff.
+use std::time::Duration;
+use crate::at::AtCommand;
+use crate::InitStepResult;
+
+/// Settings for automatic reconnection.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Commands to send to the modem after it's been reopened (after `ATE0`, which is always sent).
+    ///
+    /// These are also sent when the modem is first opened. Their results are reported in
+    /// `ReconnectEvent::Opened` and `ReconnectEvent::Reconnected`; if any of them fails (or
+    /// doesn't return `OK`), the attempt to open the modem counts as failed.
+    pub init_commands: Vec<AtCommand>,
+    /// How long to wait before the first attempt to reopen the modem.
+    pub initial_backoff: Duration,
+    /// The maximum amount of time to wait between attempts. The wait doubles after every failed
+    /// attempt, up to this limit.
+    pub max_backoff: Duration,
+    /// The number of failed attempts after which to give up, or `None` to keep trying forever.
+    ///
+    /// If the supervisor gives up, the background future exits, just as if the modem had
+    /// disconnected without a supervisor.
+    pub max_attempts: Option<u32>
+}
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            init_commands: vec![],
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_attempts: None
+        }
+    }
+}
+/// Something that happened to the connection to a supervised modem.
+///
+/// See `HuaweiModem::take_reconnect_rx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// The modem went away, for the reason given.
+    Disconnected(String),
+    /// The modem was opened for the first time, and initialised successfully.
+    Opened {
+        /// The results of each step of the initialisation sequence.
+        init: Vec<InitStepResult>
+    },
+    /// An attempt to (re)open the modem, or to initialise it afterwards, failed.
+    AttemptFailed {
+        /// The number of this attempt, starting at 1 (the first time the modem is opened counts
+        /// as attempt 1, too).
+        attempt: u32,
+        /// A description of what went wrong.
+        error: String
+    },
+    /// The modem was successfully reopened and initialised.
+    Reconnected {
+        /// The number of attempts it took.
+        attempts: u32,
+        /// The results of each step of the initialisation sequence.
+        init: Vec<InitStepResult>
+    },
+    /// The supervisor reached `max_attempts`, and gave up.
+    GaveUp
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use futures::Stream;
+    use tokio_core::reactor::Core;
+    use crate::HuaweiModem;
+    use crate::at::AtValue;
+    use crate::cmd;
+    use crate::simulator::ModemSimulator;
+
+    fn cmee() -> AtCommand {
+        AtCommand::Equals { param: "+CMEE".into(), value: AtValue::Integer(1) }
+    }
+    #[test]
+    fn reconnects_and_replays_init() {
+        let mut core = Core::new().unwrap();
+        let sims = Rc::new(RefCell::new(vec![]));
+        let sims2 = sims.clone();
+        let config = ReconnectConfig {
+            init_commands: vec![cmee()],
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let modem = HuaweiModem::new_supervised(move || {
+            let sim = ModemSimulator::new();
+            let stream = sim.stream();
+            sims2.borrow_mut().push(sim);
+            Ok(stream)
+        }, config, &core.handle()).unwrap();
+        let events = modem.take_reconnect_rx().unwrap();
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        let (ev, events) = core.run(events.into_future()).map_err(|_| ()).unwrap();
+        match ev {
+            Some(ReconnectEvent::Opened { init }) => {
+                assert_eq!(init.len(), 2);
+                assert_eq!(init[1].command, cmee());
+            },
+            x => panic!("unexpected event: {:?}", x)
+        }
+        sims.borrow()[0].hang_up();
+        let (ev, events) = core.run(events.into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(ev, Some(ReconnectEvent::Disconnected("end of stream".into())));
+        let (ev, _) = core.run(events.into_future()).map_err(|_| ()).unwrap();
+        match ev {
+            Some(ReconnectEvent::Reconnected { attempts: 1, init }) => assert_eq!(init.len(), 2),
+            x => panic!("unexpected event: {:?}", x)
+        }
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        assert_eq!(sims.borrow()[1].received_commands(), vec!["ATE0", "AT+CMEE=1", "AT+CSQ"]);
+    }
+    #[test]
+    fn disconnect_during_init() {
+        use futures_timer::Delay;
+
+        let mut core = Core::new().unwrap();
+        let sims = Rc::new(RefCell::new(vec![]));
+        let sims2 = sims.clone();
+        let cmgf = AtCommand::Equals { param: "+CMGF".into(), value: AtValue::Integer(0) };
+        let config = ReconnectConfig {
+            init_commands: vec![cmee(), cmgf],
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let modem = HuaweiModem::new_supervised(move || {
+            let sim = ModemSimulator::new();
+            if sims2.borrow().is_empty() {
+                // never answer, so the modem goes away in the middle of initialisation
+                sim.script("AT+CMEE", &[]);
+            }
+            let stream = sim.stream();
+            sims2.borrow_mut().push(sim);
+            Ok(stream)
+        }, config, &core.handle()).unwrap();
+        let events = modem.take_reconnect_rx().unwrap();
+        core.run(Delay::new(Duration::from_millis(50))).unwrap();
+        assert_eq!(sims.borrow()[0].received_commands(), vec!["ATE0", "AT+CMEE=1"]);
+        sims.borrow()[0].hang_up();
+        let events = core.run(events.take(2).collect()).unwrap();
+        assert_eq!(events[0], ReconnectEvent::Disconnected("end of stream".into()));
+        match events[1] {
+            // the first open never finished, so this still counts as the modem opening
+            ReconnectEvent::Opened { ref init } => assert_eq!(init.len(), 3),
+            ref x => panic!("unexpected event: {:?}", x)
+        }
+        assert_eq!(sims.borrow()[1].received_commands(), vec!["ATE0", "AT+CMEE=1", "AT+CMGF=0"]);
+    }
+    #[test]
+    fn failed_init_fails_the_attempt() {
+        let mut core = Core::new().unwrap();
+        let sims = Rc::new(RefCell::new(vec![]));
+        let sims2 = sims.clone();
+        let config = ReconnectConfig {
+            init_commands: vec![cmee()],
+            initial_backoff: Duration::from_millis(10),
+            max_attempts: Some(2),
+            ..Default::default()
+        };
+        let modem = HuaweiModem::new_supervised(move || {
+            let sim = ModemSimulator::new();
+            sim.script("AT+CMEE", &["ERROR"]);
+            let stream = sim.stream();
+            sims2.borrow_mut().push(sim);
+            Ok(stream)
+        }, config, &core.handle()).unwrap();
+        let events = modem.take_reconnect_rx().unwrap();
+        let res = core.run(cmd::network::get_signal_quality(&modem));
+        assert!(res.is_err());
+        let events = core.run(events.collect()).unwrap();
+        assert_eq!(events.len(), 3);
+        match events[0] {
+            ReconnectEvent::AttemptFailed { attempt: 1, .. } => {},
+            ref x => panic!("unexpected event: {:?}", x)
+        }
+        match events[1] {
+            ReconnectEvent::AttemptFailed { attempt: 2, .. } => {},
+            ref x => panic!("unexpected event: {:?}", x)
+        }
+        assert_eq!(events[2], ReconnectEvent::GaveUp);
+        for sim in sims.borrow().iter() {
+            assert_eq!(sim.received_commands(), vec!["ATE0", "AT+CMEE=1"]);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Recording and replaying the raw bytes exchanged with a modem.
+//!
+//! Wrapping a modem's stream in a `TranscriptRecorder` writes a timestamped transcript of
+//! everything sent to and received from the modem. A `TranscriptReplay` does the reverse: it
+//! pretends to be the modem from a recorded transcript, which makes it possible to turn a
+//! misbehaving modem in the field into a deterministic test case.
+//!
+//! Transcripts are plain text, one chunk of data per line:
+//!
+//! ```text
+//! # lines starting with '#' are comments
+//! 0.000000 tx "\r\nATE0\r\n"
+//! 0.001532 rx "\r\nOK\r\n"
+//! 0.002001 tx "\r\nAT+CSQ\r\n"
+//! 0.009874 rx "\r\n+CSQ: 17,99\r\n\r\nOK\r\n"
+//! ```
+//!
+//! Each line consists of the time since recording started, in seconds; the direction (`tx` for
+//! data sent to the modem, `rx` for data received from it); and the data itself, as a quoted
+//! string where `\r`, `\n`, `\"`, `\\` and `\xNN` escapes are used for anything that isn't
+//! printable ASCII.
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+use crate::errors::{HuaweiError, HuaweiResult};
+
+/// Which way some data went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent to the modem.
+    Sent,
+    /// Received from the modem.
+    Received
+}
+/// One chunk of data in a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// The time since recording started.
+    pub elapsed: Duration,
+    /// Which way the data went.
+    pub direction: Direction,
+    /// The data itself.
+    pub data: Vec<u8>
+}
+impl fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dir = match self.direction {
+            Direction::Sent => "tx",
+            Direction::Received => "rx"
+        };
+        write!(f, "{}.{:06} {} \"", self.elapsed.as_secs(), self.elapsed.subsec_micros(), dir)?;
+        for &b in self.data.iter() {
+            match b {
+                b'\r' => write!(f, "\\r")?,
+                b'\n' => write!(f, "\\n")?,
+                b'"' => write!(f, "\\\"")?,
+                b'\\' => write!(f, "\\\\")?,
+                0x20..=0x7e => write!(f, "{}", b as char)?,
+                b => write!(f, "\\x{:02X}", b)?
+            }
+        }
+        write!(f, "\"")
+    }
+}
+impl TranscriptEntry {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut parts = line.splitn(3, ' ');
+        let elapsed = parts.next().unwrap_or("");
+        let dir = parts.next().ok_or("missing direction")?;
+        let data = parts.next().ok_or("missing data")?;
+        let mut secs = elapsed.splitn(2, '.');
+        let whole: u64 = secs.next().unwrap_or("").parse()
+            .map_err(|_| format!("invalid timestamp {:?}", elapsed))?;
+        let micros: u32 = match secs.next() {
+            Some(m) => format!("{:0<6}", m).get(..6).unwrap_or("").parse()
+                .map_err(|_| format!("invalid timestamp {:?}", elapsed))?,
+            None => 0
+        };
+        let direction = match dir {
+            "tx" => Direction::Sent,
+            "rx" => Direction::Received,
+            x => return Err(format!("invalid direction {:?}", x))
+        };
+        if data.len() < 2 || !data.starts_with('"') || !data.ends_with('"') {
+            return Err("data must be a quoted string".into());
+        }
+        let mut ret = vec![];
+        let mut bytes = data[1..data.len() - 1].bytes();
+        while let Some(b) = bytes.next() {
+            if b != b'\\' {
+                ret.push(b);
+                continue;
+            }
+            match bytes.next() {
+                Some(b'r') => ret.push(b'\r'),
+                Some(b'n') => ret.push(b'\n'),
+                Some(b'"') => ret.push(b'"'),
+                Some(b'\\') => ret.push(b'\\'),
+                Some(b'x') => {
+                    let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                    let hex = ::std::str::from_utf8(&hex).ok()
+                        .and_then(|h| u8::from_str_radix(h, 16).ok())
+                        .ok_or("invalid \\x escape")?;
+                    ret.push(hex);
+                },
+                _ => return Err("invalid escape".into())
+            }
+        }
+        Ok(TranscriptEntry {
+            elapsed: Duration::new(whole, micros * 1000),
+            direction,
+            data: ret
+        })
+    }
+}
+/// A recorded transcript.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    /// The chunks of data in the transcript, in order.
+    pub entries: Vec<TranscriptEntry>
+}
+impl Transcript {
+    /// Parse a transcript written by a `TranscriptRecorder`.
+    pub fn parse(text: &str) -> HuaweiResult<Self> {
+        let mut entries = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let ent = TranscriptEntry::parse(line)
+                .map_err(|reason| HuaweiError::InvalidTranscript { line: i + 1, reason })?;
+            entries.push(ent);
+        }
+        Ok(Transcript { entries })
+    }
+}
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ent in self.entries.iter() {
+            writeln!(f, "{}", ent)?;
+        }
+        Ok(())
+    }
+}
+/// Wraps a modem's stream, writing a transcript of everything sent and received to `W`.
+///
+/// Use it like `HuaweiModem::new_from_stream(TranscriptRecorder::new(stream, file), &handle)`.
+/// Errors writing the transcript are logged, and otherwise ignored.
+pub struct TranscriptRecorder<S, W> {
+    inner: S,
+    sink: W,
+    start: Instant
+}
+impl<S, W> TranscriptRecorder<S, W> where W: Write {
+    /// Start recording the stream `inner` to `sink`.
+    pub fn new(inner: S, sink: W) -> Self {
+        Self { inner, sink, start: Instant::now() }
+    }
+    /// Stop recording, returning the wrapped stream and the sink.
+    pub fn into_inner(self) -> (S, W) {
+        (self.inner, self.sink)
+    }
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        let ent = TranscriptEntry {
+            elapsed: self.start.elapsed(),
+            direction,
+            data: data.to_owned()
+        };
+        if let Err(e) = writeln!(self.sink, "{}", ent).and_then(|_| self.sink.flush()) {
+            warn!("failed to write transcript: {}", e);
+        }
+    }
+}
+impl<S, W> Read for TranscriptRecorder<S, W> where S: Read, W: Write {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if len > 0 {
+            self.record(Direction::Received, &buf[..len]);
+        }
+        Ok(len)
+    }
+}
+impl<S, W> Write for TranscriptRecorder<S, W> where S: Write, W: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if len > 0 {
+            self.record(Direction::Sent, &buf[..len]);
+        }
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<S, W> AsyncRead for TranscriptRecorder<S, W> where S: AsyncRead, W: Write {}
+impl<S, W> AsyncWrite for TranscriptRecorder<S, W> where S: AsyncWrite, W: Write {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+struct ReplayState {
+    entries: Vec<TranscriptEntry>,
+    rx_cursor: usize,
+    rx_offset: usize,
+    tx_cursor: usize,
+    tx_offset: usize,
+    read_task: Option<Task>
+}
+impl ReplayState {
+    fn skip_to(&self, mut idx: usize, dir: Direction) -> usize {
+        while idx < self.entries.len() && self.entries[idx].direction != dir {
+            idx += 1;
+        }
+        idx
+    }
+}
+/// Pretends to be a modem, by replaying a recorded `Transcript`.
+///
+/// Data received from the modem is replayed as soon as everything that was sent before it in
+/// the transcript has been sent again, ignoring the original timings. Data sent to the replayed
+/// modem must match the transcript exactly; if it doesn't, the write fails with an error of kind
+/// `InvalidData`, which normally makes the connection to the modem fail.
+///
+/// Once the transcript runs out, the replayed modem goes quiet (rather than disconnecting).
+#[derive(Clone)]
+pub struct TranscriptReplay {
+    state: Arc<Mutex<ReplayState>>
+}
+impl TranscriptReplay {
+    /// Make a new replay of the given transcript.
+    pub fn new(transcript: Transcript) -> Self {
+        let entries = transcript.entries;
+        let mut state = ReplayState {
+            entries,
+            rx_cursor: 0,
+            rx_offset: 0,
+            tx_cursor: 0,
+            tx_offset: 0,
+            read_task: None
+        };
+        state.rx_cursor = state.skip_to(0, Direction::Received);
+        state.tx_cursor = state.skip_to(0, Direction::Sent);
+        TranscriptReplay { state: Arc::new(Mutex::new(state)) }
+    }
+    /// Get a stream that replays the transcript, for use with `HuaweiModem::new_from_stream`.
+    pub fn stream(&self) -> ReplayStream {
+        ReplayStream { state: self.state.clone() }
+    }
+    /// Returns `true` if the whole transcript has been replayed (i.e. everything in it has been
+    /// both sent and received).
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.rx_cursor >= state.entries.len() && state.tx_cursor >= state.entries.len()
+    }
+}
+/// The stream end of a `TranscriptReplay`.
+pub struct ReplayStream {
+    state: Arc<Mutex<ReplayState>>
+}
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let idx = state.rx_cursor;
+        if idx >= state.entries.len() || state.tx_cursor < idx {
+            state.read_task = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let off = state.rx_offset;
+        let len = ::std::cmp::min(buf.len(), state.entries[idx].data.len() - off);
+        buf[..len].copy_from_slice(&state.entries[idx].data[off..off + len]);
+        state.rx_offset += len;
+        if state.rx_offset >= state.entries[idx].data.len() {
+            state.rx_offset = 0;
+            state.rx_cursor = state.skip_to(idx + 1, Direction::Received);
+        }
+        Ok(len)
+    }
+}
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        for &b in buf.iter() {
+            let idx = state.tx_cursor;
+            let off = state.tx_offset;
+            let expected = match state.entries.get(idx) {
+                Some(e) => e.data.get(off).cloned(),
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("write past the end of the transcript: {:?}", buf)));
+                }
+            };
+            if expected != Some(b) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("replay diverged from transcript entry {}: expected {:?}, got {:?}",
+                                                  idx, state.entries[idx].data, buf)));
+            }
+            state.tx_offset += 1;
+            if state.tx_offset >= state.entries[idx].data.len() {
+                state.tx_offset = 0;
+                state.tx_cursor = state.skip_to(idx + 1, Direction::Sent);
+            }
+        }
+        if let Some(t) = state.read_task.take() {
+            t.notify();
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl AsyncRead for ReplayStream {}
+impl AsyncWrite for ReplayStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_core::reactor::Core;
+    use crate::HuaweiModem;
+    use crate::cmd;
+    use crate::cmd::network::{RegistrationState, SignalQuality};
+    use crate::simulator::ModemSimulator;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn entry_roundtrip() {
+        let ent = TranscriptEntry {
+            elapsed: Duration::from_micros(1_500_020),
+            direction: Direction::Received,
+            data: b"\r\n> \"\\\x1A\xFF".to_vec()
+        };
+        let line = ent.to_string();
+        assert_eq!(line, r#"1.500020 rx "\r\n> \"\\\x1A\xFF""#);
+        assert_eq!(TranscriptEntry::parse(&line).unwrap(), ent);
+        match Transcript::parse("# comment\n\n0.1 tx \"AT\"\n0.2 sideways \"\"") {
+            Err(HuaweiError::InvalidTranscript { line: 4, .. }) => {},
+            x => panic!("unexpected result: {:?}", x)
+        }
+    }
+    #[test]
+    fn record_and_replay() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        sim.set_signal_quality(SignalQuality { rssi: 17, ber: 99 });
+        let buf = SharedBuf::default();
+        let stream = TranscriptRecorder::new(sim.stream(), buf.clone());
+        let modem = HuaweiModem::new_from_stream(stream, &core.handle()).unwrap();
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        core.run(cmd::network::get_registration(&modem)).unwrap();
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let transcript = Transcript::parse(&text).unwrap();
+        assert_eq!(transcript.entries[0].data, b"\r\nATE0\r\n");
+
+        let replay = TranscriptReplay::new(transcript.clone());
+        let modem = HuaweiModem::new_from_stream(replay.stream(), &core.handle()).unwrap();
+        let sq = core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        assert_eq!(sq, SignalQuality { rssi: 17, ber: 99 });
+        let reg = core.run(cmd::network::get_registration(&modem)).unwrap();
+        assert_eq!(reg, RegistrationState::RegisteredHome);
+        assert!(replay.is_finished());
+
+        let replay = TranscriptReplay::new(transcript);
+        let modem = HuaweiModem::new_from_stream(replay.stream(), &core.handle()).unwrap();
+        assert!(core.run(cmd::network::get_registration(&modem)).is_err());
+    }
+}
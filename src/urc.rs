@@ -0,0 +1,539 @@
+//! Typed URCs (Unsolicited Result Codes).
+//!
+//! The modem sends URCs whenever something interesting happens (a new message arrives, the signal
+//! strength changes, someone calls...). `HuaweiModem::take_urc_rx` gives you these as raw
+//! `AtResponse`s; `HuaweiModem::take_typed_urc_rx` gives you a stream of `Urc`s instead, which
+//! have been parsed into something more useful. The two are independent, so you can use both at
+//! once.
+//!
+//! URCs this module doesn't know about (or that fail to parse) are passed through as
+//! `Urc::Other`.
+//!
+//! If more than one part of your program wants to see URCs, use `HuaweiModem::subscribe_urcs`
+//! instead: each subscriber gets its own `UrcSubscription`, optionally filtered by prefix, with a
+//! bounded buffer (see `SubscriptionConfig` for what happens when it fills up).
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use futures::{Stream, Poll, Async};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::{self, Task};
+use crate::at::{AtResponse, AtResultCode};
+use crate::cmd::network::{ServiceStatus, SimState};
+use crate::convert::{FromAtValue, field, fields};
+use crate::errors::{HuaweiError, HuaweiResult};
+
+/// The status of a USSD response (from `+CUSD`).
+#[repr(u8)]
+#[derive(Fail, Debug, FromPrimitive, FromAtValue, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UssdStatus {
+    /// No further user action required.
+    #[fail(display = "No further action required")]
+    NoFurtherAction = 0,
+    /// Further user action required (i.e. the network wants a reply).
+    #[fail(display = "Further action required")]
+    FurtherAction = 1,
+    /// The USSD session was terminated by the network.
+    #[fail(display = "Terminated by network")]
+    TerminatedByNetwork = 2,
+    /// Another local client has responded.
+    #[fail(display = "Other local client has responded")]
+    OtherClientResponded = 3,
+    /// Operation not supported.
+    #[fail(display = "Operation not supported")]
+    NotSupported = 4,
+    /// The network timed out.
+    #[fail(display = "Network timeout")]
+    NetworkTimeout = 5
+}
+/// A parsed URC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Urc {
+    /// A new message has been stored in the modem's memory (`+CMTI`).
+    ///
+    /// You'll only get these if you've enabled them with `cmd::sms::set_new_message_indications`.
+    NewMessage {
+        /// The memory the message was stored in (e.g. `"SM"`).
+        storage: String,
+        /// The index of the message in that memory.
+        index: u32
+    },
+    /// A new SMS status report has been stored in the modem's memory (`+CDSI`).
+    NewStatusReport {
+        /// The memory the status report was stored in.
+        storage: String,
+        /// The index of the status report in that memory.
+        index: u32
+    },
+    /// Incoming call (`RING`).
+    Ring,
+    /// Calling line identification for an incoming call (`+CLIP`).
+    CallerId {
+        /// The caller's phone number.
+        number: String,
+        /// The type of the number (see `pdu::AddressType`), if given.
+        number_type: Option<u32>
+    },
+    /// A USSD response, or a network-initiated USSD message (`+CUSD`).
+    Ussd {
+        /// The status of the USSD session.
+        status: UssdStatus,
+        /// The message text, if any.
+        ///
+        /// This is passed through as-is; depending on `dcs` and the modem, it may need decoding.
+        message: Option<String>,
+        /// The data coding scheme of the message, if given.
+        dcs: Option<u32>
+    },
+    /// The received signal strength changed (`^RSSI`, Huawei-specific).
+    ///
+    /// The value is on the same scale as `SignalQuality::rssi`.
+    SignalStrength(u32),
+    /// The system mode changed (`^MODE`, Huawei-specific).
+    ModeChange {
+        /// The new system mode.
+        mode: u32,
+        /// The new system submode, if given.
+        submode: Option<u32>
+    },
+    /// Periodic heartbeat sent by the modem (`^BOOT`, Huawei-specific).
+    Boot,
+    /// The service status changed (`^SRVST`, Huawei-specific).
+    ServiceState(ServiceStatus),
+    /// The state of the SIM card changed (`^SIMST`, Huawei-specific).
+    SimState {
+        /// The new SIM state.
+        state: SimState,
+        /// Whether the SIM is locked, if given.
+        locked: Option<bool>
+    },
+    /// Some other URC, not understood by this library.
+    Other(AtResponse)
+}
+impl Urc {
+    /// Parse a raw `AtResponse` into a `Urc`, falling back to `Urc::Other` if it isn't a
+    /// recognised URC.
+    pub fn from_response(resp: AtResponse) -> Self {
+        match Self::parse(&resp) {
+            Ok(Some(u)) => u,
+            _ => Urc::Other(resp)
+        }
+    }
+    fn parse(resp: &AtResponse) -> HuaweiResult<Option<Self>> {
+        let (param, value) = match *resp {
+            AtResponse::ResultCode(AtResultCode::Ring) => return Ok(Some(Urc::Ring)),
+            AtResponse::InformationResponse { ref param, ref response } => (param, response),
+            _ => return Ok(None)
+        };
+        let f = fields(value);
+        let ret = match param as &str {
+            "+CMTI" => Urc::NewMessage {
+                storage: field(f, 0, "NewMessage.storage")?,
+                index: field(f, 1, "NewMessage.index")?
+            },
+            "+CDSI" => Urc::NewStatusReport {
+                storage: field(f, 0, "NewStatusReport.storage")?,
+                index: field(f, 1, "NewStatusReport.index")?
+            },
+            "+CLIP" => Urc::CallerId {
+                number: field(f, 0, "CallerId.number")?,
+                number_type: field(f, 1, "CallerId.number_type")?
+            },
+            "+CUSD" => Urc::Ussd {
+                status: field(f, 0, "Ussd.status")?,
+                message: field(f, 1, "Ussd.message")?,
+                dcs: field(f, 2, "Ussd.dcs")?
+            },
+            "^RSSI" => Urc::SignalStrength(field(f, 0, "SignalStrength")?),
+            "^MODE" => Urc::ModeChange {
+                mode: field(f, 0, "ModeChange.mode")?,
+                submode: field(f, 1, "ModeChange.submode")?
+            },
+            "^BOOT" => Urc::Boot,
+            "^SRVST" => Urc::ServiceState(field(f, 0, "ServiceState")?),
+            "^SIMST" => Urc::SimState {
+                state: field(f, 0, "SimState.state")?,
+                locked: field::<Option<u32>>(f, 1, "SimState.locked")?.map(|l| l == 1)
+            },
+            _ => return Ok(None)
+        };
+        Ok(Some(ret))
+    }
+}
+impl From<AtResponse> for Urc {
+    fn from(resp: AtResponse) -> Self {
+        Urc::from_response(resp)
+    }
+}
+/// A stream of typed URCs, returned from `HuaweiModem::take_typed_urc_rx`.
+///
+/// This is a `UrcSubscription` (with the default `SubscriptionConfig`) underneath, so it only
+/// sees URCs from when it was taken, and ends when the connection to the modem goes away.
+pub struct UrcStream {
+    inner: UrcSubscription
+}
+impl UrcStream {
+    /// Get back the underlying subscription of raw URCs.
+    pub fn into_inner(self) -> UrcSubscription {
+        self.inner
+    }
+}
+impl Stream for UrcStream {
+    type Item = Urc;
+    type Error = HuaweiError;
+
+    fn poll(&mut self) -> Poll<Option<Urc>, HuaweiError> {
+        let ret = futures::try_ready!(self.inner.poll());
+        Ok(Async::Ready(ret.map(Urc::from_response)))
+    }
+}
+/// What to do when a subscriber's buffer is full, and a new URC arrives.
+///
+/// In both cases, the oldest buffered URC is discarded to make room for the new one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Silently drop the oldest URC.
+    DropOldest,
+    /// Drop the oldest URC, and make the subscription yield a `HuaweiError::UrcLagged` error
+    /// (containing the number of URCs dropped) before any more URCs.
+    ///
+    /// The subscription carries on working after the error.
+    LagError
+}
+/// Settings for a URC subscription (see `HuaweiModem::subscribe_urcs`).
+#[derive(Debug, Clone)]
+pub struct SubscriptionConfig {
+    /// Only deliver URCs whose name (e.g. `+CMTI` or `^RSSI`) starts with this prefix, or all URCs
+    /// if `None`.
+    pub prefix: Option<String>,
+    /// The maximum number of URCs to buffer for this subscriber (at least 1).
+    pub capacity: usize,
+    /// What to do when the buffer is full.
+    pub policy: OverflowPolicy
+}
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            prefix: None,
+            capacity: 64,
+            policy: OverflowPolicy::DropOldest
+        }
+    }
+}
+impl SubscriptionConfig {
+    /// Subscribe to URCs starting with `prefix`, with the default buffer settings.
+    pub fn prefix(prefix: &str) -> Self {
+        SubscriptionConfig {
+            prefix: Some(prefix.into()),
+            ..Default::default()
+        }
+    }
+}
+/// The name of a URC, for prefix matching purposes.
+fn urc_name(resp: &AtResponse) -> Option<&str> {
+    match *resp {
+        AtResponse::InformationResponse { ref param, .. } => Some(param),
+        AtResponse::Unknown(ref st) => Some(st),
+        AtResponse::ResultCode(AtResultCode::Ring) => Some("RING"),
+        AtResponse::ResultCode(AtResultCode::NoCarrier) => Some("NO CARRIER"),
+        _ => None
+    }
+}
+struct SubscriberState {
+    config: SubscriptionConfig,
+    buf: VecDeque<AtResponse>,
+    lagged: u64,
+    task: Option<Task>,
+    closed: bool,
+    /// Whether the last URC matched the prefix (so any data lines following it should be sent).
+    matched: bool
+}
+impl SubscriberState {
+    fn push(&mut self, resp: &AtResponse) {
+        if let Some(ref pfx) = self.config.prefix {
+            self.matched = match *resp {
+                AtResponse::Data(_) => self.matched,
+                _ => urc_name(resp).map(|n| n.starts_with(pfx as &str)).unwrap_or(false)
+            };
+            if !self.matched {
+                return;
+            }
+        }
+        if self.buf.len() >= cmp::max(1, self.config.capacity) {
+            self.buf.pop_front();
+            if self.config.policy == OverflowPolicy::LagError {
+                self.lagged += 1;
+            }
+        }
+        self.buf.push_back(resp.clone());
+        self.notify();
+    }
+    fn notify(&mut self) {
+        if let Some(t) = self.task.take() {
+            t.notify();
+        }
+    }
+}
+/// The number of URCs kept for `HuaweiModem::take_urc_rx` before it's been taken.
+pub(crate) const URC_BACKLOG: usize = 64;
+/// Keeps track of URC subscribers; shared between the `HuaweiModem` and its background future.
+#[derive(Default)]
+pub(crate) struct UrcHub {
+    subscribers: Vec<Weak<Mutex<SubscriberState>>>,
+    /// The sending half of the receiver from `HuaweiModem::take_urc_rx`, once it's been taken.
+    legacy: Option<UnboundedSender<AtResponse>>,
+    /// The most recent URCs, kept until the receiver from `HuaweiModem::take_urc_rx` is taken.
+    backlog: VecDeque<AtResponse>,
+    legacy_taken: bool,
+    typed_taken: bool,
+    closed: bool
+}
+impl UrcHub {
+    /// Make the unbounded receiver for `HuaweiModem::take_urc_rx`, if it hasn't been taken
+    /// already.
+    ///
+    /// Until then, only the last `URC_BACKLOG` URCs are kept (and handed over here), so they
+    /// don't pile up if nobody ever takes it.
+    pub(crate) fn take_receiver(&mut self) -> Option<UnboundedReceiver<AtResponse>> {
+        if self.legacy_taken {
+            return None;
+        }
+        self.legacy_taken = true;
+        let (tx, rx) = mpsc::unbounded();
+        for resp in self.backlog.drain(..) {
+            // can't fail; we're holding on to `rx`
+            let _ = tx.unbounded_send(resp);
+        }
+        if !self.closed {
+            self.legacy = Some(tx);
+        }
+        Some(rx)
+    }
+    /// Make the stream for `HuaweiModem::take_typed_urc_rx`, if it hasn't been taken already.
+    pub(crate) fn take_typed(&mut self) -> Option<UrcStream> {
+        if self.typed_taken {
+            return None;
+        }
+        self.typed_taken = true;
+        Some(UrcStream { inner: self.subscribe(SubscriptionConfig::default()) })
+    }
+    pub(crate) fn subscribe(&mut self, config: SubscriptionConfig) -> UrcSubscription {
+        let state = Arc::new(Mutex::new(SubscriberState {
+            config,
+            buf: VecDeque::new(),
+            lagged: 0,
+            task: None,
+            closed: self.closed,
+            matched: false
+        }));
+        self.subscribers.push(Arc::downgrade(&state));
+        UrcSubscription { state }
+    }
+    fn publish(&mut self, resp: &AtResponse) {
+        self.subscribers.retain(|sub| {
+            match sub.upgrade() {
+                Some(s) => {
+                    s.lock().unwrap().push(resp);
+                    true
+                },
+                None => false
+            }
+        });
+        if !self.legacy_taken {
+            if self.backlog.len() >= URC_BACKLOG {
+                self.backlog.pop_front();
+            }
+            self.backlog.push_back(resp.clone());
+            return;
+        }
+        let gone = match self.legacy {
+            Some(ref tx) => tx.unbounded_send(resp.clone()).is_err(),
+            None => false
+        };
+        if gone {
+            trace!("URC receiver has gone away; dropping URC");
+            self.legacy = None;
+        }
+    }
+    fn close(&mut self) {
+        self.closed = true;
+        self.legacy = None;
+        for sub in self.subscribers.drain(..) {
+            if let Some(s) = sub.upgrade() {
+                let mut s = s.lock().unwrap();
+                s.closed = true;
+                s.notify();
+            }
+        }
+    }
+}
+/// The background future's end of a `UrcHub`; ends all subscriptions when dropped.
+pub(crate) struct UrcPublisher {
+    hub: Arc<Mutex<UrcHub>>
+}
+impl UrcPublisher {
+    pub(crate) fn new(hub: Arc<Mutex<UrcHub>>) -> Self {
+        Self { hub }
+    }
+    pub(crate) fn publish(&self, resp: &AtResponse) {
+        self.hub.lock().unwrap().publish(resp);
+    }
+}
+impl Drop for UrcPublisher {
+    fn drop(&mut self) {
+        self.hub.lock().unwrap().close();
+    }
+}
+/// One subscriber's stream of URCs, returned from `HuaweiModem::subscribe_urcs`.
+///
+/// The stream ends when the connection to the modem goes away. Use
+/// `.map(Urc::from_response)` if you want typed URCs.
+pub struct UrcSubscription {
+    state: Arc<Mutex<SubscriberState>>
+}
+impl Stream for UrcSubscription {
+    type Item = AtResponse;
+    type Error = HuaweiError;
+
+    fn poll(&mut self) -> Poll<Option<AtResponse>, HuaweiError> {
+        let mut state = self.state.lock().unwrap();
+        if state.lagged > 0 {
+            let n = state.lagged;
+            state.lagged = 0;
+            return Err(HuaweiError::UrcLagged(n));
+        }
+        if let Some(r) = state.buf.pop_front() {
+            return Ok(Async::Ready(Some(r)));
+        }
+        if state.closed {
+            return Ok(Async::Ready(None));
+        }
+        state.task = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_core::reactor::Core;
+    use crate::HuaweiModem;
+    use crate::at::AtValue;
+    use crate::cmd;
+    use crate::parse::parse_response_line;
+    use crate::simulator::ModemSimulator;
+
+    fn urc(line: &str) -> Urc {
+        let (_, resp) = parse_response_line(line.as_bytes()).unwrap();
+        Urc::from_response(resp)
+    }
+    #[test]
+    fn standard_urcs() {
+        assert_eq!(urc("+CMTI: \"SM\",3"), Urc::NewMessage { storage: "SM".into(), index: 3 });
+        assert_eq!(urc("+CDSI: \"SR\",1"), Urc::NewStatusReport { storage: "SR".into(), index: 1 });
+        assert_eq!(urc("RING"), Urc::Ring);
+        assert_eq!(urc("+CLIP: \"+447700900123\",145,,,,0"), Urc::CallerId {
+            number: "+447700900123".into(),
+            number_type: Some(145)
+        });
+        assert_eq!(urc("+CUSD: 0,\"Your balance is 5.00\",15"), Urc::Ussd {
+            status: UssdStatus::NoFurtherAction,
+            message: Some("Your balance is 5.00".into()),
+            dcs: Some(15)
+        });
+        assert_eq!(urc("+CUSD: 2"), Urc::Ussd { status: UssdStatus::TerminatedByNetwork, message: None, dcs: None });
+    }
+    #[test]
+    fn huawei_urcs() {
+        assert_eq!(urc("^RSSI: 17"), Urc::SignalStrength(17));
+        assert_eq!(urc("^MODE:5,4"), Urc::ModeChange { mode: 5, submode: Some(4) });
+        assert_eq!(urc("^BOOT:20952891,0,0,0,72"), Urc::Boot);
+        assert_eq!(urc("^SRVST:2"), Urc::ServiceState(ServiceStatus::Valid));
+        assert_eq!(urc("^SIMST: 255"), Urc::SimState { state: SimState::NotPresent, locked: None });
+        assert_eq!(urc("^SIMST:1,0"), Urc::SimState { state: SimState::Valid, locked: Some(false) });
+    }
+    #[test]
+    fn unknown_urcs() {
+        let resp = AtResponse::InformationResponse { param: "+CMTI".into(), response: AtValue::Integer(3) };
+        assert_eq!(Urc::from_response(resp.clone()), Urc::Other(resp));
+        assert_eq!(urc("^SRVST: 99"), Urc::Other(AtResponse::InformationResponse {
+            param: "^SRVST".into(),
+            response: AtValue::Integer(99)
+        }));
+        assert_eq!(urc("NO CARRIER"), Urc::Other(AtResponse::ResultCode(AtResultCode::NoCarrier)));
+    }
+    #[test]
+    fn subscribers() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        let all = modem.subscribe_urcs(SubscriptionConfig::default());
+        let sms = modem.subscribe_urcs(SubscriptionConfig::prefix("+CMTI"));
+        let lag = modem.subscribe_urcs(SubscriptionConfig {
+            prefix: Some("^RSSI".into()),
+            capacity: 2,
+            policy: OverflowPolicy::LagError
+        });
+        for i in 1..4 {
+            sim.inject_urc(&format!("^RSSI: {}", i));
+        }
+        sim.inject_urc("+CMTI: \"SM\",3");
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+
+        let urcs = core.run(all.take(4).collect()).unwrap();
+        assert_eq!(urcs.len(), 4);
+        let (urc, _) = core.run(sms.into_future()).map_err(|(e, _)| e).unwrap();
+        assert_eq!(urc.map(Urc::from_response), Some(Urc::NewMessage { storage: "SM".into(), index: 3 }));
+        match core.run(lag.into_future()) {
+            Err((HuaweiError::UrcLagged(1), lag)) => {
+                let urcs = core.run(lag.take(2).collect()).unwrap();
+                let urcs: Vec<_> = urcs.into_iter().map(Urc::from_response).collect();
+                assert_eq!(urcs, vec![Urc::SignalStrength(2), Urc::SignalStrength(3)]);
+            },
+            Err((e, _)) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("subscriber should have lagged")
+        }
+        // the unbounded receiver still gets the URCs from before it was taken
+        let rx = modem.take_urc_rx().unwrap();
+        assert!(modem.take_urc_rx().is_none());
+        sim.inject_urc("+CMTI: \"SM\",4");
+        let urcs = core.run(rx.take(5).collect()).unwrap();
+        let urcs: Vec<_> = urcs.into_iter().map(Urc::from_response).collect();
+        assert_eq!(urcs[0], Urc::SignalStrength(1));
+        assert_eq!(urcs[4], Urc::NewMessage { storage: "SM".into(), index: 4 });
+    }
+    #[test]
+    fn raw_and_typed_together() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        let raw = modem.take_urc_rx().unwrap();
+        let typed = modem.take_typed_urc_rx().unwrap();
+        assert!(modem.take_typed_urc_rx().is_none());
+        sim.inject_urc("+CMTI: \"SM\",3");
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+
+        let (urc, _) = core.run(raw.into_future()).map_err(|_| ()).unwrap();
+        assert_eq!(urc, Some(AtResponse::InformationResponse {
+            param: "+CMTI".into(),
+            response: AtValue::Array(vec![AtValue::String("SM".into()), AtValue::Integer(3)])
+        }));
+        let (urc, _) = core.run(typed.into_future()).map_err(|(e, _)| e).unwrap();
+        assert_eq!(urc, Some(Urc::NewMessage { storage: "SM".into(), index: 3 }));
+    }
+    #[test]
+    fn backlog_is_bounded() {
+        let mut core = Core::new().unwrap();
+        let sim = ModemSimulator::new();
+        let modem = HuaweiModem::new_from_stream(sim.stream(), &core.handle()).unwrap();
+        for i in 0..(URC_BACKLOG + 2) {
+            sim.inject_urc(&format!("^RSSI: {}", i));
+        }
+        core.run(cmd::network::get_signal_quality(&modem)).unwrap();
+        let rx = modem.take_urc_rx().unwrap();
+        drop(modem);
+        let urcs = core.run(rx.collect()).unwrap();
+        let urcs: Vec<_> = urcs.into_iter().map(Urc::from_response).collect();
+        assert_eq!(urcs.len(), URC_BACKLOG);
+        assert_eq!(urcs[0], Urc::SignalStrength(2));
+    }
+}